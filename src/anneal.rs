@@ -0,0 +1,148 @@
+use crate::objective::{Candidate, Objective, Tolerance};
+use crate::{Ingredient, IngredientRatio, SharedState};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// Temperatures at the start and end of each restart, in objective units.
+const START_TEMPERATURE: f64 = 50.0;
+const END_TEMPERATURE: f64 = 0.05;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub objective: Objective,
+    pub iterations: usize,
+    pub restarts: usize,
+    pub seed: u64,
+    pub tolerance: Tolerance,
+}
+
+/**
+ * Applies one random move to the candidate: add an ingredient, remove one,
+ * or swap one for another. Returns false if the move didn't fit.
+ */
+fn mutate<R: Rng>(
+    candidate: &mut Candidate,
+    pool: &[(Ingredient, Option<usize>)],
+    target: &IngredientRatio,
+    rng: &mut R,
+) -> bool {
+    match rng.gen_range(0..3) {
+        0 => {
+            let j = rng.gen_range(0..pool.len());
+            if !candidate.can_add(pool, target, j) {
+                return false;
+            }
+            candidate.add(pool, j);
+        }
+        1 => match candidate.random_ingredient(rng) {
+            Some(i) => candidate.remove(pool, i),
+            None => return false,
+        },
+        _ => return candidate.swap(pool, target, rng),
+    }
+    true
+}
+
+/**
+ * One simulated annealing run from a random starting recipe.
+ * Returns the best recipe seen that passes the tolerance checks, with its score,
+ * and whether it ran every iteration rather than being stopped.
+ */
+pub fn anneal(
+    pool: &[(Ingredient, Option<usize>)],
+    target: &IngredientRatio,
+    settings: &Settings,
+    seed: u64,
+    stop: &AtomicBool,
+) -> (Option<(f64, Candidate)>, bool) {
+    if pool.is_empty() {
+        return (None, true);
+    }
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut current = Candidate::random(pool, target, &mut rng);
+    let mut current_score = settings
+        .objective
+        .score(target, &current.attributes, current.cost);
+    let mut best: Option<(f64, Candidate)> = None;
+
+    for step in 0..settings.iterations {
+        if stop.load(Ordering::Relaxed) {
+            return (best, false);
+        }
+        let progress = step as f64 / settings.iterations as f64;
+        let temperature = START_TEMPERATURE * (END_TEMPERATURE / START_TEMPERATURE).powf(progress);
+
+        let mut next = current.clone();
+        if !mutate(&mut next, pool, target, &mut rng) {
+            continue;
+        }
+        let next_score = settings
+            .objective
+            .score(target, &next.attributes, next.cost);
+        if next_score > current_score
+            && rng.gen::<f64>() >= ((current_score - next_score) / temperature).exp()
+        {
+            continue;
+        }
+        current = next;
+        current_score = next_score;
+
+        let improved = match &best {
+            Some((best_score, _)) => current_score < *best_score,
+            None => true,
+        };
+        if improved && settings.tolerance.accepts(target, &current).is_some() {
+            best = Some((current_score, current.clone()));
+        }
+    }
+    (best, true)
+}
+
+/**
+ * Runs the restarts on `threads` worker threads and collects the distinct best recipes.
+ * Returns whether every restart ran to the end.
+ */
+pub fn solve(shared_state: &SharedState, settings: Settings, threads: usize) -> bool {
+    let next_restart = AtomicUsize::new(0);
+    let complete = AtomicBool::new(true);
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, settings.restarts.max(1)) {
+            scope.spawn(|| loop {
+                let restart = next_restart.fetch_add(1, Ordering::Relaxed);
+                if restart >= settings.restarts {
+                    break;
+                }
+                let (best, finished) = anneal(
+                    shared_state.ingredients,
+                    &shared_state.target,
+                    &settings,
+                    settings.seed.wrapping_add(restart as u64),
+                    &shared_state.stop,
+                );
+                if !finished {
+                    complete.store(false, Ordering::Relaxed);
+                }
+                if let Some((_, candidate)) = best {
+                    results.lock().unwrap().push((restart, candidate));
+                }
+            });
+        }
+    });
+
+    // Same order whichever thread finished first.
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(restart, _)| *restart);
+    let mut distinct: Vec<Candidate> = Vec::new();
+    for (_, candidate) in results {
+        if distinct.iter().all(|c| c.counts != candidate.counts) {
+            distinct.push(candidate);
+        }
+    }
+    let mut acc = shared_state.acc.lock().unwrap();
+    acc.extend(distinct.iter().map(|c| c.recipe(shared_state.ingredients)));
+    complete.into_inner()
+}
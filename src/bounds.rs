@@ -0,0 +1,215 @@
+use crate::{Ingredient, IngredientRatio, Magimins};
+
+/**
+ * The most of each magimin that a few more ingredients from a suffix of the
+ * sorted pool can add, used to prune recipes that can't reach the ratio.
+ */
+pub struct Reach {
+    // most[s][dim][k] is the largest amount of magimin `dim` that k
+    // ingredients from pool[s..] can add.
+    most: Vec<[Vec<usize>; 5]>,
+}
+
+impl Reach {
+    pub fn new(pool: &[(Ingredient, Option<usize>)], count: usize) -> Reach {
+        let most = (0..=pool.len())
+            .map(|s| {
+                let mut most: [Vec<usize>; 5] = Default::default();
+                for (dim, most) in most.iter_mut().enumerate() {
+                    let mut values: Vec<usize> = pool[s..]
+                        .iter()
+                        .flat_map(|(ingredient, available)| {
+                            let n = available.unwrap_or(count).min(count);
+                            std::iter::repeat_n(ingredient.magimins.as_array()[dim], n)
+                        })
+                        .collect();
+                    values.sort_unstable_by(|a, b| b.cmp(a));
+                    values.resize(count, 0);
+                    most.push(0);
+                    for value in values {
+                        most.push(most.last().unwrap() + value);
+                    }
+                }
+                most
+            })
+            .collect();
+        Reach { most }
+    }
+
+    fn most(&self, pool_len: usize, dim: usize, slots: usize) -> usize {
+        self.most[self.most.len() - 1 - pool_len][dim][slots]
+    }
+
+    /**
+     * True if no way of adding up to `slots` ingredients from the pool gets
+     * the magimins to an exact multiple of the target ratio within its bounds.
+     */
+    pub fn exact_unreachable(
+        &self,
+        target: &IngredientRatio,
+        magimins: &Magimins,
+        pool_len: usize,
+        slots: usize,
+    ) -> bool {
+        let current = magimins.as_array();
+        let ratio = target.magimins.as_array();
+        let ratio_total = target.magimins.total() as f64;
+        // Magimins never go away, so the recipe needs at least this many
+        // multiples of the ratio to cover every magimin it already has.
+        let mut multiple = target.min as f64 / ratio_total;
+        for dim in 0..5 {
+            if ratio[dim] > 0 {
+                multiple = multiple.max(current[dim] as f64 / ratio[dim] as f64);
+            }
+        }
+        if multiple * ratio_total > target.max as f64 + 1e-9 {
+            return true;
+        }
+        (0..5).any(|dim| {
+            let most = current[dim] + self.most(pool_len, dim, slots);
+            (most as f64) < multiple * ratio[dim] as f64 - 1e-9
+        })
+    }
+
+    /**
+     * True if some magimin can't get within `max_deviation` percentage points
+     * of its target share, whatever up to `slots` more ingredients are added.
+     */
+    pub fn share_unreachable(
+        &self,
+        target: &IngredientRatio,
+        magimins: &Magimins,
+        pool_len: usize,
+        slots: usize,
+        max_deviation: f64,
+    ) -> bool {
+        let current = magimins.as_array();
+        let ratio = target.magimins.as_array();
+        let ratio_total = target.magimins.total() as f64;
+        let least_total = magimins.total().max(target.min) as f64;
+        (0..5).any(|dim| {
+            let least_share = ratio[dim] as f64 / ratio_total - max_deviation / 100.0;
+            let most = current[dim] + self.most(pool_len, dim, slots);
+            least_share > 0.0 && (most as f64) < least_share * least_total - 1e-9
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reach;
+    use crate::progress::{Counters, Progress, SearchSpace};
+    use crate::test_support::{names, pool};
+    use crate::{
+        enumerate, exhaustive_callback, Ingredient, IngredientRatio, PotionRecipe, Recipe,
+        RecipeState, SharedState, SpecificState, Stability,
+    };
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+
+    fn target() -> IngredientRatio {
+        let mut target = Recipe::HEALTH.target();
+        target.count = 6;
+        target.min = 80;
+        target.max = 240;
+        target
+    }
+
+    /**
+     * Every recipe the search with the reachability bounds finds.
+     */
+    fn search(
+        pool: &[(Ingredient, Option<usize>)],
+        specific_state: &SpecificState,
+    ) -> Vec<Vec<String>> {
+        let shared_state = SharedState {
+            ingredients: pool,
+            target: target(),
+            acc: Mutex::new(Vec::new()),
+            max_cost: None,
+            start_with: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Progress::default()),
+        };
+        let space = SearchSpace::new(pool, shared_state.target.count);
+        let reach = Reach::new(pool, shared_state.target.count);
+        let mut counters = Counters::default();
+        let mut cb = exhaustive_callback(
+            &shared_state,
+            specific_state,
+            &space,
+            &reach,
+            &shared_state.acc,
+        );
+        for start in 0..pool.len() {
+            enumerate(
+                &pool[start..],
+                shared_state.target.count,
+                &mut Vec::new(),
+                RecipeState::default(),
+                &mut |ingredients, state, pool| cb(ingredients, state, pool, &mut counters),
+            );
+        }
+        drop(cb);
+        assert!(counters.unreachable > 0, "the bounds never pruned anything");
+        names(&shared_state.acc.into_inner().unwrap())
+    }
+
+    /**
+     * Every recipe that passes `accepts`, found without pruning anything.
+     */
+    fn every(
+        pool: &[(Ingredient, Option<usize>)],
+        accepts: impl Fn(&RecipeState) -> bool,
+    ) -> Vec<Vec<String>> {
+        let target = target();
+        let mut found = Vec::new();
+        for start in 0..pool.len() {
+            enumerate(
+                &pool[start..],
+                target.count,
+                &mut Vec::new(),
+                RecipeState::default(),
+                &mut |ingredients, state, _| {
+                    let total = state.attributes.magimins.total();
+                    if (target.min..=target.max).contains(&total) && accepts(state) {
+                        found.push(PotionRecipe::found(ingredients, state));
+                    }
+                    true
+                },
+            );
+        }
+        names(&found)
+    }
+
+    #[test]
+    fn exact_bounds_keep_every_recipe() {
+        let (pool, target) = (pool(), target());
+        let expected = every(&pool, |state| {
+            state
+                .attributes
+                .satisfying_ratio(&target)
+                .is_some_and(|multiple| multiple > 0)
+        });
+        assert!(!expected.is_empty());
+        assert_eq!(search(&pool, &SpecificState::Exact), expected);
+    }
+
+    #[test]
+    fn share_bounds_keep_every_recipe() {
+        let (pool, target) = (pool(), target());
+        let (max_deviation, min_stability) = (10.0, Stability::Stable);
+        let expected = every(&pool, |state| {
+            state
+                .attributes
+                .tolerated_stability(&target, max_deviation, min_stability)
+                .is_some()
+        });
+        assert!(!expected.is_empty());
+        let specific_state = SpecificState::Approximate {
+            max_deviation,
+            min_stability,
+        };
+        assert_eq!(search(&pool, &specific_state), expected);
+    }
+}
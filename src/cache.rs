@@ -0,0 +1,109 @@
+use crate::{utils, Ingredient, IngredientRatio, PotionRecipe};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/**
+ * The results of a finished search, saved under a hash of what it searched.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    // The whole key, in case two keys hash the same.
+    key: String,
+    recipes: Vec<Vec<String>>,
+}
+
+/**
+ * Results of earlier searches, one file per search.
+ */
+pub struct Cache {
+    pub dir: PathBuf,
+}
+
+/**
+ * Describes a search: every ingredient in the pool as searched, the target
+ * and the mode with its settings. Any change to one of them changes the key.
+ */
+pub fn key(pool: &[(Ingredient, Option<usize>)], target: &IngredientRatio, mode: &str) -> String {
+    let mut key = format!(
+        "{} {}\n{:?} count {} min {} max {}\n{}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        target.magimins.as_array(),
+        target.count,
+        target.min,
+        target.max,
+        mode
+    );
+    for (ingredient, available) in pool.iter() {
+        key += &format!("{:?} {:?}\n", ingredient, available);
+    }
+    key
+}
+
+impl Cache {
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.json", utils::fnv1a(key.as_bytes())))
+    }
+
+    /**
+     * The cached recipes for the key, if there are any.
+     */
+    pub fn load<'a>(
+        &self,
+        key: &str,
+        pool: &'a [(Ingredient, Option<usize>)],
+    ) -> Option<Vec<PotionRecipe<'a>>> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        let entry: Entry = serde_json::from_str(&contents).ok()?;
+        if entry.key != key {
+            return None;
+        }
+        PotionRecipe::from_names(pool, &entry.recipes).ok()
+    }
+
+    pub fn store(&self, key: &str, recipes: &[PotionRecipe]) -> Result<(), String> {
+        let entry = Entry {
+            key: key.to_owned(),
+            recipes: recipes.iter().map(|recipe| recipe.names()).collect(),
+        };
+        let path = self.path(key);
+        let contents = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&path, contents))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /**
+     * Deletes the entries older than `older_than`, or all of them.
+     * Returns how many entries were deleted and how many bytes that freed.
+     */
+    pub fn prune(&self, older_than: Option<Duration>) -> Result<(usize, u64), String> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok((0, 0)),
+        };
+        let (mut count, mut bytes) = (0, 0);
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .unwrap_or_default();
+            if older_than.is_some_and(|older_than| age < older_than) {
+                continue;
+            }
+            fs::remove_file(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            count += 1;
+            bytes += metadata.len();
+        }
+        Ok((count, bytes))
+    }
+}
@@ -0,0 +1,262 @@
+use crate::parallel::Subtree;
+use crate::{Ingredient, PotionRecipe};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/**
+ * What a search looks for. Resuming is only safe if this is the same.
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchKey {
+    pub ingredients: String,
+    pub ingredients_hash: u64,
+    pub recipe: String,
+    pub magimins: [usize; 5],
+    pub count: usize,
+    pub min: usize,
+    pub max: usize,
+    pub mode: String,
+    // Only set for approximate searches, exact ones don't depend on them.
+    pub max_deviation: Option<f64>,
+    pub min_stability: Option<String>,
+    pub keep_dominated: bool,
+    // Required even though it's an Option, so that a key written before it
+    // existed is refused rather than read as having no budget.
+    #[serde(deserialize_with = "Option::deserialize")]
+    pub max_cost: Option<usize>,
+    pub start_with: Vec<String>,
+}
+
+/**
+ * A snapshot of an exhaustive search: the subtrees left to search and
+ * every recipe found outside of them.
+ * The recipes are appended to a file of their own, one per line, so that a
+ * checkpoint only writes those found since the last one. Each is a list of
+ * indices into the pool, which is kept to make sure it's the same on resuming.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub key: SearchKey,
+    // Starting ingredients whose whole subtree has been searched, for reference.
+    pub completed: Vec<String>,
+    pub frontier: Vec<Subtree>,
+    pub pool: Vec<String>,
+    // How much of the recipes file this checkpoint covers, in recipes and bytes.
+    // Anything after that was written after the checkpoint.
+    pub saved: Saved,
+    #[serde(skip)]
+    pub recipes: Vec<Vec<usize>>,
+}
+
+/**
+ * The recipes file next to a checkpoint.
+ */
+pub fn recipes_path(path: &Path) -> PathBuf {
+    path.with_extension("recipes.jsonl")
+}
+
+impl Checkpoint {
+    pub fn load(path: &PathBuf) -> Result<Checkpoint, String> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut checkpoint: Checkpoint =
+            serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let recipes_path = recipes_path(path);
+        let file =
+            File::open(&recipes_path).map_err(|e| format!("{}: {}", recipes_path.display(), e))?;
+        for line in BufReader::new(file.take(checkpoint.saved.bytes)).lines() {
+            let recipe = line
+                .map_err(|e| e.to_string())
+                .and_then(|line| serde_json::from_str(&line).map_err(|e| e.to_string()))
+                .map_err(|e| format!("{}: {}", recipes_path.display(), e))?;
+            checkpoint.recipes.push(recipe);
+        }
+        if checkpoint.recipes.len() != checkpoint.saved.recipes {
+            return Err(format!(
+                "{} holds {} recipes, not the {} the checkpoint saved",
+                recipes_path.display(),
+                checkpoint.recipes.len(),
+                checkpoint.saved.recipes
+            ));
+        }
+        Ok(checkpoint)
+    }
+
+    /**
+     * Refuses to resume a search for something else.
+     */
+    pub fn check(&self, key: &SearchKey) -> Result<(), String> {
+        if self.key.ingredients_hash != key.ingredients_hash {
+            return Err(if self.key.ingredients == key.ingredients {
+                format!(
+                    "{} changed since the checkpoint was written",
+                    key.ingredients
+                )
+            } else {
+                format!(
+                    "the checkpoint was written for {}, not {}",
+                    self.key.ingredients, key.ingredients
+                )
+            });
+        }
+        if self.key != *key {
+            return Err(format!(
+                "the checkpoint was written for a different target:\n\t{:?}\nnot\n\t{:?}",
+                self.key, key
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+     * The checkpoint's recipes, made of the pool's ingredients again.
+     */
+    pub fn recipes<'a>(
+        &self,
+        pool: &'a [(Ingredient, Option<usize>)],
+    ) -> Result<Vec<PotionRecipe<'a>>, String> {
+        if !self
+            .pool
+            .iter()
+            .eq(pool.iter().map(|(ingredient, _)| &ingredient.name))
+        {
+            return Err(
+                "the checkpoint was written for a different pool of ingredients".to_owned(),
+            );
+        }
+        self.recipes
+            .iter()
+            .map(|recipe| {
+                let ingredients = recipe
+                    .iter()
+                    .map(|&i| pool.get(i).map(|(ingredient, _)| ingredient))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("the checkpoint has a recipe of unknown ingredients")?;
+                Ok(PotionRecipe::new(ingredients))
+            })
+            .collect()
+    }
+}
+
+/**
+ * How many of the search's recipes have been saved, and the bytes they take.
+ */
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Saved {
+    pub recipes: usize,
+    pub bytes: u64,
+}
+
+/**
+ * Where and how often a search saves its checkpoints.
+ */
+pub struct Writer {
+    pub path: PathBuf,
+    pub interval: Duration,
+    pub key: SearchKey,
+    // Also keeps two saves from writing at once.
+    saved: Mutex<Saved>,
+}
+
+impl Writer {
+    pub fn new(path: PathBuf, interval: Duration, key: SearchKey) -> Writer {
+        Writer {
+            path,
+            interval,
+            key,
+            saved: Mutex::new(Saved::default()),
+        }
+    }
+
+    /**
+     * Carries on writing the checkpoint a search resumed from, which saved
+     * the first recipes the search has.
+     */
+    pub fn resume(path: PathBuf, interval: Duration, key: SearchKey, saved: Saved) -> Writer {
+        Writer {
+            saved: Mutex::new(saved),
+            ..Writer::new(path, interval, key)
+        }
+    }
+
+    /**
+     * Appends the recipes found since the last save, then saves the rest of
+     * the checkpoint next to its final path first, so that a crash while
+     * writing leaves the previous checkpoint intact.
+     * Returns how many recipes the checkpoint holds.
+     */
+    pub fn write(
+        &self,
+        pool: &[(Ingredient, Option<usize>)],
+        frontier: &[Subtree],
+        acc: &Mutex<Vec<PotionRecipe>>,
+    ) -> Result<usize, String> {
+        let mut saved = self.saved.lock().unwrap();
+        // Copied out, so the search isn't held up while they're written.
+        let new: Vec<Vec<&Ingredient>> = acc.lock().unwrap()[saved.recipes..]
+            .iter()
+            .map(|recipe| recipe.ingredients.clone())
+            .collect();
+        let index: HashMap<&str, usize> = pool
+            .iter()
+            .enumerate()
+            .map(|(i, (ingredient, _))| (ingredient.name.as_str(), i))
+            .collect();
+        let mut lines = Vec::new();
+        for ingredients in new.iter() {
+            let indices: Vec<usize> = ingredients.iter().map(|i| index[i.name.as_str()]).collect();
+            serde_json::to_writer(&mut lines, &indices).map_err(|e| e.to_string())?;
+            lines.push(b'\n');
+        }
+        let recipes_path = recipes_path(&self.path);
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&recipes_path)
+            .and_then(|mut file| {
+                // Dropping whatever was written after the last checkpoint.
+                file.set_len(saved.bytes)?;
+                file.seek(SeekFrom::End(0))?;
+                file.write_all(&lines)?;
+                // Before the checkpoint says they're there.
+                file.sync_data()
+            })
+            .map_err(|e| format!("{}: {}", recipes_path.display(), e))?;
+        saved.recipes += new.len();
+        saved.bytes += lines.len() as u64;
+
+        // Every subtree's prefix starts with the ingredients the recipes start with.
+        let head = self.key.start_with.len();
+        let completed = (0..pool.len())
+            .filter(|&i| {
+                !frontier
+                    .iter()
+                    .any(|subtree| subtree.prefix.get(head).unwrap_or(&subtree.start) == &i)
+            })
+            .map(|i| pool[i].0.name.clone())
+            .collect();
+        let checkpoint = Checkpoint {
+            key: self.key.clone(),
+            completed,
+            frontier: frontier.to_vec(),
+            pool: pool
+                .iter()
+                .map(|(ingredient, _)| ingredient.name.clone())
+                .collect(),
+            saved: *saved,
+            recipes: Vec::new(),
+        };
+        let contents = serde_json::to_string(&checkpoint).map_err(|e| e.to_string())?;
+        let temporary = self.path.with_extension("tmp");
+        fs::write(&temporary, contents)
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        Ok(saved.recipes)
+    }
+}
@@ -0,0 +1,589 @@
+use crate::bounds::Reach;
+use crate::parallel::Subtree;
+use crate::progress::{self, Counters, Progress, SearchSpace};
+use crate::{
+    cache, candidates, exhaustive_callback, seed, utils, Ingredient, IngredientRatio, PotionRecipe,
+    Recipe, SharedState, SolveAlgorithm, SpecificState, Stability,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// The coordinator hands out subtrees of at most 1 / CHUNKS of the search,
+// so that the work spreads over every worker and a lost worker loses little.
+const CHUNKS: u64 = 4096;
+// How often a waiting connection checks whether the search is over.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/**
+ * Everything a worker needs to run the coordinator's search.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Setup {
+    // The contents of the ingredients file, which the workers may not have.
+    pub ingredients: String,
+    pub recipe: Recipe,
+    pub mode: SolveAlgorithm,
+    pub max_deviation: f64,
+    pub min_stability: Stability,
+    pub max_cost: Option<usize>,
+    pub keep_dominated: bool,
+    // The ingredients every recipe starts with, one name per ingredient.
+    pub start_with: Vec<String>,
+}
+
+/**
+ * What the coordinator and its workers send each other, one JSON object per line.
+ */
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    // Coordinator to worker.
+    Setup(Setup),
+    Search { id: u64, subtree: Subtree },
+    Shutdown,
+    // Worker to coordinator.
+    Ready { pool: u64, threads: usize },
+    Recipe { id: u64, ingredients: Vec<String> },
+    Searched { id: u64, counters: Counters },
+}
+
+fn encode(message: &Message, buffer: &mut Vec<u8>) {
+    serde_json::to_writer(&mut *buffer, message).unwrap();
+    buffer.push(b'\n');
+}
+
+fn send(stream: &mut TcpStream, message: &Message) -> Result<(), String> {
+    let mut line = Vec::new();
+    encode(message, &mut line);
+    stream.write_all(&line).map_err(|e| e.to_string())
+}
+
+/**
+ * Sends messages as soon as they're written: they're small and each side
+ * waits on the other's, so batching them up only adds latency.
+ */
+fn connected(stream: &TcpStream) -> Result<(), String> {
+    stream.set_nodelay(true).map_err(|e| e.to_string())
+}
+
+/**
+ * Reads messages off a connection, keeping partial lines across read timeouts.
+ */
+struct Receiver {
+    reader: BufReader<TcpStream>,
+    line: Vec<u8>,
+}
+
+impl Receiver {
+    fn new(stream: TcpStream) -> Receiver {
+        Receiver {
+            reader: BufReader::new(stream),
+            line: Vec::new(),
+        }
+    }
+
+    /**
+     * The next message, or None if none came in before the read timeout.
+     */
+    fn receive(&mut self) -> Result<Option<Message>, String> {
+        match self.reader.read_until(b'\n', &mut self.line) {
+            Ok(_) if !self.line.ends_with(b"\n") => {
+                return Err("the connection was closed".to_owned())
+            }
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+        let message = serde_json::from_slice(&self.line).map_err(|e| e.to_string());
+        self.line.clear();
+        message.map(Some)
+    }
+}
+
+/**
+ * Identifies the pool a search runs on, so that a worker that built a
+ * different one is turned away instead of returning wrong recipes.
+ */
+fn pool_hash(pool: &[(Ingredient, Option<usize>)], target: &IngredientRatio) -> u64 {
+    utils::fnv1a(cache::key(pool, target, "").as_bytes())
+}
+
+/**
+ * The subtrees not handed out yet, and how many are out with workers.
+ */
+struct Work {
+    queue: Vec<Subtree>,
+    out: usize,
+}
+
+struct Coordinator<'s, 'a> {
+    shared_state: &'s SharedState<'a>,
+    specific_state: &'s SpecificState,
+    setup: &'s Setup,
+    space: &'s SearchSpace,
+    reach: &'s Reach,
+    pool_hash: u64,
+    // Subtrees bigger than this are split before being handed out.
+    chunk: u64,
+    work: Mutex<Work>,
+    // Signalled whenever a subtree is searched or handed back.
+    changed: Condvar,
+}
+
+impl Coordinator<'_, '_> {
+    fn done(&self) -> bool {
+        let work = self.work.lock().unwrap();
+        self.shared_state.stop.load(Ordering::Relaxed) || (work.queue.is_empty() && work.out == 0)
+    }
+
+    /**
+     * The next subtree to hand out, splitting big ones here first.
+     * The nodes visited while splitting are counted in `counters`.
+     */
+    fn take(&self, counters: &mut Counters) -> Option<Subtree> {
+        let (pool, progress) = (self.shared_state.ingredients, &self.shared_state.progress);
+        let mut work = self.work.lock().unwrap();
+        while !self.shared_state.stop.load(Ordering::Relaxed) {
+            let subtree = work.queue.pop()?;
+            if subtree.size(self.space) <= self.chunk {
+                work.out += 1;
+                return Some(subtree);
+            }
+            let mut check = exhaustive_callback(
+                self.shared_state,
+                self.specific_state,
+                self.space,
+                self.reach,
+                &self.shared_state.acc,
+            );
+            let children = subtree.split(
+                pool,
+                self.shared_state.target.count,
+                &mut |candidate, state, pool| {
+                    progress.tick(counters);
+                    check(candidate, state, pool, counters)
+                },
+            );
+            progress.add_subtrees(children.len());
+            progress.subtree_done(counters.worker);
+            work.queue.extend(children);
+        }
+        None
+    }
+
+    /**
+     * Hands subtrees back to the queue, for another worker to search.
+     */
+    fn give_back(&self, subtrees: impl Iterator<Item = Subtree>) {
+        let mut work = self.work.lock().unwrap();
+        for subtree in subtrees {
+            work.queue.push(subtree);
+            work.out -= 1;
+        }
+        self.changed.notify_all();
+    }
+
+    /**
+     * Keeps a worker busy until the search is over. Whatever it was
+     * searching when it's lost goes back to the queue.
+     */
+    fn serve(&self, stream: TcpStream, peer: SocketAddr, worker: usize) {
+        let mut out = HashMap::new();
+        let result = self.talk(stream, peer, worker, &mut out);
+        self.give_back(out.into_values());
+        if let Err(e) = result {
+            log::warn!("Lost the worker at {}: {}", peer, e);
+        }
+    }
+
+    fn talk(
+        &self,
+        stream: TcpStream,
+        peer: SocketAddr,
+        worker: usize,
+        out: &mut HashMap<u64, Subtree>,
+    ) -> Result<(), String> {
+        let pool = self.shared_state.ingredients;
+        let progress = &self.shared_state.progress;
+        stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(POLL_INTERVAL)))
+            .map_err(|e| e.to_string())?;
+        connected(&stream)?;
+        let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+        let mut receiver = Receiver::new(stream);
+
+        send(&mut writer, &Message::Setup(self.setup.clone()))?;
+        let threads = loop {
+            match receiver.receive()? {
+                None if self.done() => return Ok(()),
+                None => {}
+                Some(Message::Ready { pool, threads }) if pool == self.pool_hash => {
+                    break threads.max(1)
+                }
+                Some(Message::Ready { .. }) => {
+                    send(&mut writer, &Message::Shutdown)?;
+                    return Err(
+                        "it searches a different pool of ingredients, is it the same version?"
+                            .to_owned(),
+                    );
+                }
+                Some(message) => return Err(format!("unexpected {:?}", message)),
+            }
+        };
+        log::debug!(
+            "The worker at {} joined, searching on {} threads",
+            peer,
+            threads
+        );
+
+        // Recipes of subtrees still being searched, only kept once the whole
+        // subtree is, so that a lost worker's recipes aren't counted twice.
+        let mut found: HashMap<u64, Vec<Vec<String>>> = HashMap::new();
+        let mut counters = Counters {
+            worker,
+            ..Default::default()
+        };
+        let mut next_id = 0;
+        loop {
+            if self.shared_state.stop.load(Ordering::Relaxed) {
+                return send(&mut writer, &Message::Shutdown);
+            }
+            // Two per thread, so the worker has the next one queued while it
+            // sends back what it found.
+            while out.len() < 2 * threads {
+                let Some(subtree) = self.take(&mut counters) else {
+                    break;
+                };
+                log::trace!("Sending subtree {} to {}: {:?}", next_id, peer, subtree);
+                out.insert(next_id, subtree.clone());
+                send(
+                    &mut writer,
+                    &Message::Search {
+                        id: next_id,
+                        subtree,
+                    },
+                )?;
+                next_id += 1;
+            }
+            progress.flush(&mut counters);
+
+            if out.is_empty() {
+                if self.done() {
+                    return send(&mut writer, &Message::Shutdown);
+                }
+                // Another worker may still be lost and give its subtrees back.
+                let work = self.work.lock().unwrap();
+                drop(self.changed.wait_timeout(work, POLL_INTERVAL).unwrap());
+                continue;
+            }
+
+            match receiver.receive()? {
+                None => {}
+                Some(Message::Recipe { id, ingredients }) => {
+                    found.entry(id).or_default().push(ingredients)
+                }
+                Some(Message::Searched {
+                    id,
+                    counters: mut searched,
+                }) => {
+                    if out.remove(&id).is_none() {
+                        return Err(format!("it searched subtree {}, which it wasn't given", id));
+                    }
+                    let recipes =
+                        PotionRecipe::from_names(pool, &found.remove(&id).unwrap_or_default())?;
+                    log::trace!(
+                        "{} searched subtree {}, finding {} recipes",
+                        peer,
+                        id,
+                        recipes.len()
+                    );
+                    self.shared_state.acc.lock().unwrap().extend(recipes);
+                    searched.worker = worker;
+                    progress.flush(&mut searched);
+                    progress.subtree_done(worker);
+                    self.work.lock().unwrap().out -= 1;
+                    self.changed.notify_all();
+                }
+                Some(message) => return Err(format!("unexpected {:?}", message)),
+            }
+        }
+    }
+}
+
+/**
+ * Starts a worker process on this machine, searching on one thread.
+ */
+fn spawn_worker(address: &SocketAddr) -> Result<Child, String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    Command::new(exe)
+        .args([
+            "--quiet",
+            "--threads",
+            "1",
+            "work",
+            "--connect",
+            &address.to_string(),
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("couldn't start a local worker: {}", e))
+}
+
+/**
+ * Runs an exhaustive search on the workers that connect to `listener`,
+ * collecting the recipes they find into the shared state.
+ * Starts `spawn_local` workers on this machine first.
+ * Returns whether every subtree was searched.
+ */
+pub fn coordinate(
+    shared_state: &SharedState,
+    specific_state: &SpecificState,
+    setup: &Setup,
+    listener: TcpListener,
+    spawn_local: usize,
+) -> Result<bool, String> {
+    let (pool, target) = (shared_state.ingredients, &shared_state.target);
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    log::info!("Waiting for workers on {}", address);
+    let children = (0..spawn_local)
+        .map(|_| spawn_worker(&address))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let space = SearchSpace::new(pool, target.count);
+    let roots = Subtree::roots(pool.len(), &shared_state.start_with);
+    // Like a local search, count what the roots leave out as covered.
+    let left: u64 = roots.iter().map(|subtree| subtree.size(&space)).sum();
+    shared_state.progress.flush(&mut Counters {
+        skipped: space.total() - left,
+        ..Default::default()
+    });
+    shared_state.progress.add_subtrees(roots.len());
+    let coordinator = Coordinator {
+        shared_state,
+        specific_state,
+        setup,
+        space: &space,
+        reach: &Reach::new(pool, target.count),
+        pool_hash: pool_hash(pool, target),
+        chunk: space.total() / CHUNKS + 1,
+        work: Mutex::new(Work {
+            // Popped from the back, so the biggest subtrees go first.
+            queue: roots.into_iter().rev().collect(),
+            out: 0,
+        }),
+        changed: Condvar::new(),
+    };
+    let finished = Arc::new(AtomicBool::new(false));
+    let reporter = progress::spawn_reporter(
+        shared_state.progress.clone(),
+        space.total(),
+        finished.clone(),
+    );
+
+    thread::scope(|scope| {
+        let coordinator = &coordinator;
+        let mut workers = 0;
+        while !coordinator.done() {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    let worker = workers;
+                    workers += 1;
+                    scope.spawn(move || coordinator.serve(stream, peer, worker));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => log::warn!("Couldn't accept a worker: {}", e),
+            }
+        }
+    });
+
+    finished.store(true, Ordering::Relaxed);
+    reporter.join().unwrap();
+    for mut child in children {
+        let _ = child.wait();
+    }
+    let work = coordinator.work.lock().unwrap();
+    Ok(work.queue.is_empty() && work.out == 0)
+}
+
+/**
+ * Connects to a coordinator and searches the subtrees it hands out on
+ * `threads` threads, sending back every recipe found, until it's done.
+ */
+pub fn work(address: &str, threads: usize) -> Result<(), String> {
+    let stream = TcpStream::connect(address).map_err(|e| format!("{}: {}", address, e))?;
+    connected(&stream)?;
+    let mut receiver = Receiver::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let setup = match receiver.receive()? {
+        Some(Message::Setup(setup)) => setup,
+        Some(Message::Shutdown) => return Ok(()),
+        message => return Err(format!("expected the search setup, got {:?}", message)),
+    };
+
+    let target = setup.recipe.target();
+    let (mut ingredients, equivalences) = candidates(
+        Ingredient::parse(setup.ingredients.lines().map(str::to_owned)),
+        &target,
+        setup.mode,
+        setup.keep_dominated,
+        &setup.start_with,
+    );
+    let start_with = seed(&mut ingredients, &equivalences, &target, &setup.start_with)?;
+    let shared_state = SharedState {
+        ingredients: &ingredients,
+        target,
+        acc: Mutex::new(Vec::new()),
+        max_cost: setup.max_cost,
+        start_with,
+        stop: Arc::new(AtomicBool::new(false)),
+        progress: Arc::new(Progress::default()),
+    };
+    let specific_state = SpecificState::new(setup.mode, setup.max_deviation, setup.min_stability);
+    let space = SearchSpace::new(&ingredients, shared_state.target.count);
+    let reach = Reach::new(&ingredients, shared_state.target.count);
+
+    let writer = Mutex::new(stream);
+    send(
+        &mut writer.lock().unwrap(),
+        &Message::Ready {
+            pool: pool_hash(&ingredients, &shared_state.target),
+            threads,
+        },
+    )?;
+    log::info!("Searching for {} on {} threads", address, threads);
+
+    let (jobs, queue) = mpsc::channel::<(u64, Subtree)>();
+    let queue = Mutex::new(queue);
+    thread::scope(|scope| {
+        let jobs = jobs;
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let Ok((id, subtree)) = queue.lock().unwrap().recv() else {
+                    break;
+                };
+                let found = Mutex::new(Vec::new());
+                let mut counters = Counters::default();
+                let mut check =
+                    exhaustive_callback(&shared_state, &specific_state, &space, &reach, &found);
+                subtree.search(
+                    &ingredients,
+                    shared_state.target.count,
+                    &mut |candidate, state, pool| {
+                        if shared_state.stop.load(Ordering::Relaxed) {
+                            return false;
+                        }
+                        counters.nodes += 1;
+                        check(candidate, state, pool, &mut counters)
+                    },
+                );
+                drop(check);
+
+                let mut buffer = Vec::new();
+                for recipe in found.into_inner().unwrap() {
+                    let ingredients = recipe.names();
+                    encode(&Message::Recipe { id, ingredients }, &mut buffer);
+                }
+                encode(&Message::Searched { id, counters }, &mut buffer);
+                // The coordinator is gone, or done with this worker.
+                if writer.lock().unwrap().write_all(&buffer).is_err() {
+                    break;
+                }
+            });
+        }
+
+        // Dropping `jobs` on the way out lets the searching threads finish.
+        loop {
+            match receiver.receive() {
+                Ok(Some(Message::Search { id, subtree })) => jobs.send((id, subtree)).unwrap(),
+                Ok(Some(Message::Shutdown)) => {
+                    shared_state.stop.store(true, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Ok(Some(message)) => return Err(format!("unexpected {:?}", message)),
+                Ok(None) => {}
+                Err(e) => {
+                    shared_state.stop.store(true, Ordering::Relaxed);
+                    return Err(format!("lost the coordinator: {}", e));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{coordinate, work, Setup};
+    use crate::parallel::Subtree;
+    use crate::progress::Progress;
+    use crate::test_support::{catalog_lines, names};
+    use crate::{
+        candidates, solve_exhaustive, Ingredient, Recipe, SearchOptions, SharedState,
+        SolveAlgorithm, SpecificState, Stability,
+    };
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn coordinated_search_finds_what_a_local_one_does() {
+        let setup = Setup {
+            ingredients: catalog_lines().collect::<Vec<_>>().join("\n"),
+            recipe: Recipe::HEALTH,
+            mode: SolveAlgorithm::APPROXIMATE,
+            max_deviation: 10.0,
+            min_stability: Stability::Stable,
+            max_cost: None,
+            keep_dominated: false,
+            start_with: Vec::new(),
+        };
+        let target = setup.recipe.target();
+        let (pool, _) = candidates(
+            Ingredient::parse(setup.ingredients.lines().map(str::to_owned)),
+            &target,
+            setup.mode,
+            setup.keep_dominated,
+            &setup.start_with,
+        );
+        let specific_state =
+            SpecificState::new(setup.mode, setup.max_deviation, setup.min_stability);
+        let shared_state = || SharedState {
+            ingredients: &pool,
+            target: target.clone(),
+            acc: Mutex::new(Vec::new()),
+            max_cost: None,
+            start_with: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Progress::default()),
+        };
+
+        let local = shared_state();
+        let options = SearchOptions {
+            threads: 2,
+            roots: Subtree::roots(pool.len(), &[]),
+            checkpoint: None,
+        };
+        assert!(solve_exhaustive(&local, &specific_state, options));
+        let expected = names(&local.acc.into_inner().unwrap());
+        assert!(!expected.is_empty(), "nothing to compare against");
+
+        let distributed = shared_state();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::scope(|scope| {
+            let worker = scope.spawn(|| work(&address, 3));
+            assert!(coordinate(&distributed, &specific_state, &setup, listener, 0).unwrap());
+            worker.join().unwrap().unwrap();
+        });
+        assert_eq!(names(&distributed.acc.into_inner().unwrap()), expected);
+    }
+}
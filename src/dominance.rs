@@ -0,0 +1,308 @@
+use crate::Ingredient;
+use std::collections::HashMap;
+
+/**
+ * Ingredients that only differ by name, searched as a single ingredient
+ * under the representative's name.
+ */
+#[derive(Clone, Debug)]
+pub struct Equivalence {
+    pub representative: String,
+    // The other ingredients folded into the representative.
+    pub members: Vec<String>,
+    pub available: Option<usize>,
+}
+
+/**
+ * What the pre-filter removed from the pool before searching.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Prefilter {
+    // (dominated, dominating) ingredient names.
+    pub dominated: Vec<(String, String)>,
+    pub equivalences: Vec<Equivalence>,
+}
+
+fn interchangeable(a: &Ingredient, b: &Ingredient) -> bool {
+    a.magimins == b.magimins && a.senses() == b.senses() && a.price == b.price
+}
+
+/**
+ * Any recipe using `b` is at least as good with `a` instead: same magimins,
+ * no more expensive, no worse on any sense, and enough of it for a whole cauldron.
+ */
+fn dominates(
+    a: &(Ingredient, Option<usize>),
+    b: &(Ingredient, Option<usize>),
+    count: usize,
+) -> bool {
+    let ((a, a_available), (b, _)) = (a, b);
+    a.magimins == b.magimins
+        && a.price <= b.price
+        && a.senses()
+            .iter()
+            .zip(b.senses().iter())
+            .all(|(a, b)| a >= b)
+        && a_available.unwrap_or(count) >= count
+}
+
+/**
+ * Folds interchangeable ingredients together, then drops the ones another
+ * ingredient dominates, except those named in `keep`. Keeps the pool's order.
+ */
+pub fn prefilter(
+    pool: Vec<(Ingredient, Option<usize>)>,
+    count: usize,
+    keep: &[String],
+) -> (Vec<(Ingredient, Option<usize>)>, Prefilter) {
+    let mut report = Prefilter::default();
+
+    let mut merged: Vec<(Ingredient, Option<usize>)> = Vec::with_capacity(pool.len());
+    let mut classes: Vec<Vec<String>> = Vec::with_capacity(pool.len());
+    for (ingredient, available) in pool {
+        match merged
+            .iter()
+            .position(|(other, _)| interchangeable(other, &ingredient))
+        {
+            Some(i) => {
+                let total = &mut merged[i].1;
+                *total = total.zip(available).map(|(a, b)| a + b);
+                classes[i].push(ingredient.name);
+            }
+            None => {
+                merged.push((ingredient, available));
+                classes.push(Vec::new());
+            }
+        }
+    }
+    for ((ingredient, available), members) in merged.iter().zip(classes.iter()) {
+        if !members.is_empty() {
+            report.equivalences.push(Equivalence {
+                representative: ingredient.name.clone(),
+                members: members.clone(),
+                available: *available,
+            });
+        }
+    }
+
+    let mut kept = Vec::with_capacity(merged.len());
+    for (i, candidate) in merged.iter().enumerate() {
+        let named = |name: &String| keep.contains(name);
+        if named(&candidate.0.name) || classes[i].iter().any(named) {
+            kept.push(candidate.clone());
+            continue;
+        }
+        let dominating = merged
+            .iter()
+            .enumerate()
+            .find(|(j, other)| *j != i && dominates(other, candidate, count));
+        match dominating {
+            Some((_, (other, _))) => report
+                .dominated
+                .push((candidate.0.name.clone(), other.name.clone())),
+            None => kept.push(candidate.clone()),
+        }
+    }
+    (kept, report)
+}
+
+impl Prefilter {
+    /**
+     * What was merged and pruned, a line each.
+     */
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for equivalence in self.equivalences.iter() {
+            lines.push(format!(
+                "Merged {} into {}: identical magimins, senses and price ({} available)",
+                equivalence.members.join(", "),
+                equivalence.representative,
+                equivalence
+                    .available
+                    .map_or("unlimited".to_owned(), |n| n.to_string()),
+            ));
+        }
+        for (dominated, by) in self.dominated.iter() {
+            lines.push(format!(
+                "Pruned {}: {} has the same magimins, costs no more and has senses at least as good",
+                dominated, by
+            ));
+        }
+        lines
+    }
+
+    pub fn log(&self) {
+        for line in self.lines() {
+            log::info!("{}", line);
+        }
+    }
+}
+
+/**
+ * Which ingredients can stand in for each other in a recipe without changing
+ * its attributes or cost, whether they were merged before the search or not.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Substitutions {
+    // Every interchangeable name, by the name of the first one in the pool.
+    classes: HashMap<String, Vec<String>>,
+    representative: HashMap<String, String>,
+}
+
+impl Substitutions {
+    pub fn new(
+        pool: &[(Ingredient, Option<usize>)],
+        equivalences: &[Equivalence],
+    ) -> Substitutions {
+        let mut substitutions = Substitutions::default();
+        for (i, (ingredient, _)) in pool.iter().enumerate() {
+            let first = &pool[..=i]
+                .iter()
+                .find(|(other, _)| interchangeable(other, ingredient))
+                .unwrap()
+                .0
+                .name;
+            substitutions
+                .classes
+                .entry(first.clone())
+                .or_default()
+                .push(ingredient.name.clone());
+            substitutions
+                .representative
+                .insert(ingredient.name.clone(), first.clone());
+        }
+        for equivalence in equivalences.iter() {
+            let first = substitutions.representative[&equivalence.representative].clone();
+            let class = substitutions.classes.get_mut(&first).unwrap();
+            class.extend(equivalence.members.iter().cloned());
+        }
+        substitutions
+    }
+
+    /**
+     * The same for every recipe that only differs by interchangeable ingredients.
+     */
+    pub fn key<'a>(&'a self, ingredients: &[&'a Ingredient]) -> Vec<&'a str> {
+        let mut key: Vec<&str> = ingredients
+            .iter()
+            .map(|i| self.representative[&i.name].as_str())
+            .collect();
+        key.sort_unstable();
+        key
+    }
+
+    /**
+     * The groups of interchangeable ingredients the recipe uses.
+     */
+    pub fn used(&self, ingredients: &[&Ingredient]) -> Vec<&[String]> {
+        let mut used: Vec<&[String]> = Vec::new();
+        for ingredient in ingredients.iter() {
+            let class = self.classes[&self.representative[&ingredient.name]].as_slice();
+            if class.len() > 1 && !used.contains(&class) {
+                used.push(class);
+            }
+        }
+        used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prefilter, Substitutions};
+    use crate::test_support::pool;
+    use crate::Ingredient;
+
+    fn find<'a>(pool: &'a [(Ingredient, Option<usize>)], name: &str) -> &'a Ingredient {
+        &pool
+            .iter()
+            .find(|(ingredient, _)| ingredient.name == name)
+            .unwrap()
+            .0
+    }
+
+    fn kept(pool: &[(Ingredient, Option<usize>)]) -> Vec<&str> {
+        pool.iter()
+            .map(|(ingredient, _)| ingredient.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn prefilter_merges_and_prunes() {
+        let (pool, report) = prefilter(pool(), 6, &[]);
+        assert_eq!(
+            kept(&pool),
+            [
+                "Giantseed",
+                "Slimeberry",
+                "Wispcap",
+                "Marshmoss",
+                "Trollcap",
+                "Boneroot",
+                "Emberleaf",
+                "Dewbead"
+            ]
+        );
+        // Slimeberry dominates Slimepod, which dominates Slimerind: both go,
+        // and Slimerind is put down to Slimeberry, which is still searched.
+        assert_eq!(
+            report.lines(),
+            [
+                "Merged Trollstool into Trollcap: identical magimins, senses and price (unlimited available)",
+                "Merged Dewdrop into Dewbead: identical magimins, senses and price (5 available)",
+                "Pruned Slimepod: Slimeberry has the same magimins, costs no more and has senses at least as good",
+                "Pruned Slimerind: Slimeberry has the same magimins, costs no more and has senses at least as good",
+            ]
+        );
+    }
+
+    #[test]
+    fn prefilter_needs_a_whole_cauldron_of_the_dominating_ingredient() {
+        let mut pool = pool();
+        pool.iter_mut()
+            .find(|(ingredient, _)| ingredient.name == "Slimeberry")
+            .unwrap()
+            .1 = Some(4);
+        let (pool, report) = prefilter(pool, 6, &[]);
+        assert!(kept(&pool).contains(&"Slimepod"));
+        assert_eq!(
+            report.dominated,
+            [("Slimerind".to_owned(), "Slimepod".to_owned())]
+        );
+    }
+
+    #[test]
+    fn prefilter_keeps_named_ingredients() {
+        let keep = ["Slimerind".to_owned(), "Dewdrop".to_owned()];
+        let (pool, report) = prefilter(pool(), 6, &keep);
+        assert!(kept(&pool).contains(&"Slimerind"));
+        assert!(kept(&pool).contains(&"Dewbead"));
+        assert_eq!(
+            report.dominated,
+            [("Slimepod".to_owned(), "Slimeberry".to_owned())]
+        );
+    }
+
+    #[test]
+    fn substitutions_group_merged_and_unmerged_ingredients() {
+        // Merged before the search, the recipe only holds the representative.
+        let (merged, report) = prefilter(pool(), 6, &[]);
+        let substitutions = Substitutions::new(&merged, &report.equivalences);
+        let recipe = [find(&merged, "Trollcap"), find(&merged, "Boneroot")];
+        assert_eq!(
+            substitutions.used(&recipe),
+            [["Trollcap".to_owned(), "Trollstool".to_owned()].as_slice()]
+        );
+
+        // With --keep-dominated, recipes that only swap one for the other share a key.
+        let pool = pool();
+        let substitutions = Substitutions::new(&pool, &[]);
+        let cap = [find(&pool, "Trollcap"), find(&pool, "Dewdrop")];
+        let stool = [find(&pool, "Trollstool"), find(&pool, "Dewbead")];
+        assert_eq!(substitutions.key(&cap), substitutions.key(&stool));
+        assert_eq!(substitutions.used(&cap), substitutions.used(&stool));
+        assert_ne!(
+            substitutions.key(&cap),
+            substitutions.key(&[find(&pool, "Trollcap"), find(&pool, "Emberleaf")])
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::objective::{Candidate, Objective, Tolerance};
+use crate::{rms, Ingredient, IngredientRatio, SharedState};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Individuals compared when picking each parent.
+const TOURNAMENT_SIZE: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub objective: Objective,
+    pub population: usize,
+    pub generations: usize,
+    pub mutation_rate: f64,
+    pub seed: u64,
+    pub tolerance: Tolerance,
+}
+
+/**
+ * Error, cost and negated sense score: the terms the objective trades off,
+ * all lower is better.
+ */
+fn terms(target: &IngredientRatio, candidate: &Candidate) -> [f64; 3] {
+    let magimins = &candidate.attributes.magimins;
+    [
+        rms(
+            (target.max / &target.magimins).as_array(),
+            target.max,
+            magimins.as_array(),
+        ),
+        candidate.cost as f64,
+        -candidate.attributes.sense_score() as f64,
+    ]
+}
+
+fn dominates(a: &[f64; 3], b: &[f64; 3]) -> bool {
+    a.iter().zip(b.iter()).all(|(a, b)| a <= b) && a != b
+}
+
+/**
+ * The acceptable recipes seen so far that no other is better than on every term.
+ */
+#[derive(Default)]
+struct ParetoFront {
+    members: Vec<([f64; 3], Candidate)>,
+}
+
+impl ParetoFront {
+    fn insert(&mut self, terms: [f64; 3], candidate: &Candidate) {
+        if self
+            .members
+            .iter()
+            .any(|(t, c)| dominates(t, &terms) || c.counts == candidate.counts)
+        {
+            return;
+        }
+        self.members.retain(|(t, _)| !dominates(&terms, t));
+        self.members.push((terms, candidate.clone()));
+    }
+}
+
+fn tournament<'p, R: Rng>(scored: &'p [(f64, Candidate)], rng: &mut R) -> &'p Candidate {
+    let mut best = &scored[rng.gen_range(0..scored.len())];
+    for _ in 1..TOURNAMENT_SIZE {
+        let other = &scored[rng.gen_range(0..scored.len())];
+        if other.0 < best.0 {
+            best = other;
+        }
+    }
+    &best.1
+}
+
+/**
+ * Splices the parents' ingredient counts at a random point, then drops random
+ * ingredients until the child fits in the cauldron again. Ingredients over the
+ * cauldron's count are dropped before the magimins are added up, as twice the
+ * ingredients a search checked the pool for could overflow them.
+ */
+fn crossover<R: Rng>(
+    pool: &[(Ingredient, Option<usize>)],
+    target: &IngredientRatio,
+    a: &Candidate,
+    b: &Candidate,
+    rng: &mut R,
+) -> Candidate {
+    let point = rng.gen_range(0..=pool.len());
+    let mut counts: Vec<usize> = a.counts[..point]
+        .iter()
+        .chain(b.counts[point..].iter())
+        .copied()
+        .collect();
+    let mut len: usize = counts.iter().sum();
+    while len > target.count {
+        // Weighted by how many of each there are, like Candidate::random_ingredient.
+        let mut n = rng.gen_range(0..len);
+        let i = counts
+            .iter()
+            .position(|&count| {
+                if n < count {
+                    return true;
+                }
+                n -= count;
+                false
+            })
+            .unwrap();
+        counts[i] -= 1;
+        len -= 1;
+    }
+    let mut child = Candidate::from_counts(pool, counts);
+    while child.attributes.magimins.total() > target.max {
+        let i = child.random_ingredient(rng).unwrap();
+        child.remove(pool, i);
+    }
+    child
+}
+
+/**
+ * Evolves a population of recipes. Returns the Pareto front of acceptable
+ * recipes over error, cost and sense score, and whether it bred every
+ * generation rather than being stopped.
+ */
+pub fn evolve(
+    pool: &[(Ingredient, Option<usize>)],
+    target: &IngredientRatio,
+    settings: &Settings,
+    stop: &AtomicBool,
+) -> (Vec<Candidate>, bool) {
+    if pool.is_empty() || settings.population == 0 {
+        return (Vec::new(), true);
+    }
+    let mut rng = ChaCha8Rng::seed_from_u64(settings.seed);
+    let mut population: Vec<Candidate> = (0..settings.population)
+        .map(|_| Candidate::random(pool, target, &mut rng))
+        .collect();
+    let mut front = ParetoFront::default();
+    let mut complete = true;
+
+    for _ in 0..settings.generations {
+        if stop.load(Ordering::Relaxed) {
+            complete = false;
+            break;
+        }
+
+        let mut scored: Vec<(f64, Candidate)> = population
+            .drain(..)
+            .map(|candidate| {
+                let score = settings
+                    .objective
+                    .score(target, &candidate.attributes, candidate.cost);
+                (score, candidate)
+            })
+            .collect();
+        for (_, candidate) in scored.iter() {
+            if settings.tolerance.accepts(target, candidate).is_some() {
+                front.insert(terms(target, candidate), candidate);
+            }
+        }
+
+        // Keep the best individual as is, breed the rest.
+        scored.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        population.push(scored[0].1.clone());
+        while population.len() < settings.population {
+            let a = tournament(&scored, &mut rng);
+            let b = tournament(&scored, &mut rng);
+            let mut child = crossover(pool, target, a, b, &mut rng);
+            if rng.gen::<f64>() < settings.mutation_rate {
+                child.swap(pool, target, &mut rng);
+            }
+            population.push(child);
+        }
+    }
+
+    let front = front
+        .members
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect();
+    (front, complete)
+}
+
+/**
+ * Returns whether the solver bred every generation.
+ */
+pub fn solve(shared_state: &SharedState, settings: Settings) -> bool {
+    let (results, complete) = evolve(
+        shared_state.ingredients,
+        &shared_state.target,
+        &settings,
+        &shared_state.stop,
+    );
+    let mut acc = shared_state.acc.lock().unwrap();
+    acc.extend(results.iter().map(|c| c.recipe(shared_state.ingredients)));
+    complete
+}
@@ -0,0 +1,35 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+/**
+ * Writes diagnostics to stderr, so that stdout only has the results.
+ */
+struct Stderr;
+
+impl Log for Stderr {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Stderr = Stderr;
+
+/**
+ * Logs errors only when quiet, and more detail with each -v.
+ */
+pub fn init(quiet: bool, verbose: u8) {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    });
+}
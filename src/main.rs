@@ -1,15 +1,37 @@
+mod anneal;
+mod bounds;
+mod cache;
+mod checkpoint;
+mod distributed;
+mod dominance;
+mod genetic;
+mod logging;
+mod objective;
+mod output;
+mod parallel;
+mod progress;
+mod repl;
+mod suggest;
+#[cfg(test)]
+mod test_support;
+mod tui;
 mod utils;
 
 use ::regex;
 use ::regex::Regex;
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
 use lazy_static::lazy_static;
-use std::array;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::ops;
-use std::sync::atomic;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 lazy_static! {
     static ref NAME_RE: Regex = regex!(r"(^|\s)([a-zA-Z]+)\s");
@@ -17,28 +39,47 @@ lazy_static! {
     static ref SENSE_RE: Regex = regex!(r"([+|-])(taste|feel|sight|smell|sound)");
     static ref PRICE_RE: Regex = regex!(r"\$(\d+)");
     static ref NUM_AVAILABLE_RE: Regex = regex!(r"x(\d+)");
+    static ref RECIPE_RE: Regex = regex!(r"(?:(\d+)\s*x\s*)?([a-zA-Z]+)");
 }
 
-#[derive(Default, Clone, Debug, Eq, Ord)]
+// Magimins a to e sit in lanes 0 to 4 and their total in lane 5,
+// so that adding two recipes also adds up their totals.
+const TOTAL_LANE: usize = 5;
+// The most magimins a lane, and so a potion, can hold.
+const MAX_MAGIMINS: usize = u16::MAX as usize;
+
+/**
+ * Amounts of each magimin, packed in 16-bit lanes so that adding and comparing
+ * them compiles down to a couple of vector instructions.
+ */
+#[derive(Default, Clone, Eq, PartialEq)]
 pub struct Magimins {
-    a: usize,
-    b: usize,
-    c: usize,
-    d: usize,
-    e: usize,
+    lanes: [u16; 8],
 }
 
 impl Magimins {
     pub fn total(&self) -> usize {
-        self.a + self.b + self.c + self.d + self.e
+        self.lanes[TOTAL_LANE] as usize
     }
 
     pub fn new(a: usize, b: usize, c: usize, d: usize, e: usize) -> Magimins {
-        Magimins { a, b, c, d, e }
+        let lane = |m: usize| u16::try_from(m).expect("too many magimins");
+        Magimins {
+            lanes: [
+                lane(a),
+                lane(b),
+                lane(c),
+                lane(d),
+                lane(e),
+                lane(a + b + c + d + e),
+                0,
+                0,
+            ],
+        }
     }
 
     pub fn as_array(&self) -> [usize; 5] {
-        [self.a, self.b, self.c, self.d, self.e]
+        std::array::from_fn(|i| self.lanes[i] as usize)
     }
 
     pub fn from_array(magimins: [usize; 5]) -> Magimins {
@@ -50,29 +91,69 @@ impl Magimins {
             magimins[4],
         )
     }
+
+    /**
+     * Per-magimin deviation, in percentage points, between this potion's
+     * share of each magimin and the share the target ratio asks for.
+     */
+    pub fn deviation(&self, ratio: &Magimins) -> [f64; 5] {
+        let sm = self.as_array();
+        let tm = ratio.as_array();
+        let s_total = self.total().max(1) as f64;
+        let t_total = ratio.total().max(1) as f64;
+        let mut deviation = [0.0; 5];
+        for i in 0..sm.len() {
+            deviation[i] = (100.0 * (sm[i] as f64 / s_total - tm[i] as f64 / t_total)).abs();
+        }
+        deviation
+    }
+
+    pub fn max_deviation(&self, ratio: &Magimins) -> f64 {
+        self.deviation(ratio).iter().fold(0.0, |m, d| d.max(m))
+    }
 }
 
 impl fmt::Display for Magimins {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "a:{}, b:{}, c:{}, d:{}, e:{}",
-            self.a, self.b, self.c, self.d, self.e
-        )
+        let [a, b, c, d, e] = self.as_array();
+        write!(f, "a:{}, b:{}, c:{}, d:{}, e:{}", a, b, c, d, e)
+    }
+}
+
+impl fmt::Debug for Magimins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e] = self.as_array();
+        f.debug_struct("Magimins")
+            .field("a", &a)
+            .field("b", &b)
+            .field("c", &c)
+            .field("d", &d)
+            .field("e", &e)
+            .finish()
     }
 }
 
 impl ops::Add<&Magimins> for &Magimins {
     type Output = Magimins;
 
+    #[inline]
     fn add(self, rhs: &Magimins) -> Magimins {
-        Magimins::new(
-            self.a + rhs.a,
-            self.b + rhs.b,
-            self.c + rhs.c,
-            self.d + rhs.d,
-            self.e + rhs.e,
-        )
+        // Saturates rather than wraps, so that an overfull cauldron still reads
+        // as over any maximum. Pool::new keeps searches from getting there.
+        Magimins {
+            lanes: std::array::from_fn(|i| self.lanes[i].saturating_add(rhs.lanes[i])),
+        }
+    }
+}
+
+impl ops::Sub<&Magimins> for &Magimins {
+    type Output = Magimins;
+
+    #[inline]
+    fn sub(self, rhs: &Magimins) -> Magimins {
+        Magimins {
+            lanes: std::array::from_fn(|i| self.lanes[i] - rhs.lanes[i]),
+        }
     }
 }
 
@@ -84,35 +165,34 @@ impl ops::Div<&Magimins> for usize {
         if total == 0 {
             return Magimins::new(0, 0, 0, 0, 0);
         }
-        return Magimins::new(
-            self * rhs.a / total,
-            self * rhs.b / total,
-            self * rhs.c / total,
-            self * rhs.d / total,
-            self * rhs.e / total,
-        );
+        let [a, b, c, d, e] = rhs.as_array();
+        Magimins::new(
+            self * a / total,
+            self * b / total,
+            self * c / total,
+            self * d / total,
+            self * e / total,
+        )
     }
 }
 
-impl PartialEq for Magimins {
+impl PartialOrd for Magimins {
     #[inline]
-    fn eq(&self, other: &Self) -> bool {
-        self.a == other.a
-            && self.b == other.b
-            && self.c == other.c
-            && self.d == other.d
-            && self.e == other.e
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl PartialOrd for Magimins {
+impl Ord for Magimins {
     #[inline]
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.total().partial_cmp(&other.total())
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.lanes[TOTAL_LANE]
+            .cmp(&other.lanes[TOTAL_LANE])
+            .then_with(|| self.lanes[..TOTAL_LANE].cmp(&other.lanes[..TOTAL_LANE]))
     }
 }
 
-#[derive(Debug, Eq, Ord, Clone)]
+#[derive(Debug, Eq, Clone)]
 pub struct Ingredient {
     name: String,
 
@@ -139,13 +219,19 @@ impl PartialEq for Ingredient {
 impl PartialOrd for Ingredient {
     #[inline]
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ingredient {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         // Inverse sort.
-        let cmp = other.mutamin.partial_cmp(&self.mutamin);
-        if cmp == Some(std::cmp::Ordering::Equal) {
-            other.sense_score().partial_cmp(&self.sense_score())
-        } else {
-            cmp
-        }
+        other
+            .mutamin
+            .cmp(&self.mutamin)
+            .then_with(|| other.sense_score().cmp(&self.sense_score()))
+            .then_with(|| self.name.cmp(&other.name))
     }
 }
 
@@ -154,8 +240,19 @@ impl Ingredient {
         self.taste + self.feel + self.sight + self.smell + self.sound
     }
 
+    pub fn senses(&self) -> [isize; 5] {
+        [self.taste, self.feel, self.sight, self.smell, self.sound]
+    }
+
     pub fn load(filename: &str) -> Vec<(Ingredient, Option<usize>)> {
-        utils::get_input(filename)
+        Ingredient::parse(utils::get_input(filename))
+    }
+
+    /**
+     * Parses the lines of an ingredients file.
+     */
+    pub fn parse(lines: impl Iterator<Item = String>) -> Vec<(Ingredient, Option<usize>)> {
+        lines
             .filter(|line| !line.starts_with("#") && !line.starts_with("//") && line.len() > 1)
             .map(|line| {
                 let mut a = 0;
@@ -236,16 +333,26 @@ pub struct PotionAttributes {
     sound: isize,
 }
 
+// Each tier's name, the magimins it starts at and those each of its stars need.
+const TIERS: [(&str, [usize; 6]); 6] = [
+    ("Minor", [0, 10, 20, 30, 40, 50]),
+    ("Common", [60, 75, 90, 105, 115, 130]),
+    ("Greater", [150, 170, 195, 215, 235, 260]),
+    ("Grand", [290, 315, 345, 370, 400, 430]),
+    ("Superior", [470, 505, 545, 580, 620, 660]),
+    ("Masterwork", [720, 800, 875, 960, 1040, 1100]),
+];
+
 impl PotionAttributes {
     fn satisfying_ratio(&self, target: &IngredientRatio) -> Option<usize> {
         if self.magimins.total() == 0 {
             return Some(0);
         }
-        let sm = self.magimins.as_array();
-        let tm = target.magimins.as_array();
+        let sm = &self.magimins.lanes;
+        let tm = &target.magimins.lanes;
         let mut target_ratio = 0;
         let mut mismatch = false;
-        for i in 0..sm.len() {
+        for i in 0..TOTAL_LANE {
             if sm[i] == 0 && tm[i] == 0 {
                 continue;
             }
@@ -258,7 +365,7 @@ impl PotionAttributes {
                 continue;
             }
 
-            mismatch |= sm[i] == 0 && tm[i] != 0 || sm[i] % tm[i] > 0;
+            mismatch |= sm[i] == 0 && tm[i] != 0 || !sm[i].is_multiple_of(tm[i]);
 
             if target_ratio == 0 {
                 target_ratio = sm[i] / tm[i];
@@ -267,7 +374,7 @@ impl PotionAttributes {
             }
         }
 
-        Some(if mismatch { 0 } else { target_ratio })
+        Some(if mismatch { 0 } else { target_ratio as usize })
     }
 }
 
@@ -286,6 +393,21 @@ impl ops::Add<&Ingredient> for &PotionAttributes {
     }
 }
 
+impl ops::Sub<&Ingredient> for &PotionAttributes {
+    type Output = PotionAttributes;
+
+    fn sub(self, rhs: &Ingredient) -> PotionAttributes {
+        PotionAttributes {
+            magimins: &self.magimins - &rhs.magimins,
+            taste: self.taste - rhs.taste,
+            feel: self.feel - rhs.feel,
+            sight: self.sight - rhs.sight,
+            smell: self.smell - rhs.smell,
+            sound: self.sound - rhs.sound,
+        }
+    }
+}
+
 impl PotionAttributes {
     fn clamp_sense(s: isize) -> isize {
         if s < 0 {
@@ -305,24 +427,109 @@ impl PotionAttributes {
             + PotionAttributes::clamp_sense(self.sound)
     }
 
+    fn tier_index(&self) -> usize {
+        let total = self.magimins.total();
+        TIERS
+            .iter()
+            .rposition(|(_, thresholds)| thresholds[0] <= total)
+            .unwrap()
+    }
+
     pub fn tier(&self) -> &'static str {
+        TIERS[self.tier_index()].0
+    }
+
+    /**
+     * The stars within the tier, from 0 to 5.
+     */
+    pub fn stars(&self) -> usize {
         let total = self.magimins.total();
-        if total < 60 {
-            return "Minor";
-        }
-        if total < 150 {
-            return "Common";
+        let thresholds = &TIERS[self.tier_index()].1;
+        thresholds.iter().filter(|&&t| t <= total).count() - 1
+    }
+
+    pub fn stability(&self, target: &IngredientRatio) -> Stability {
+        if let Some(1..) = self.satisfying_ratio(target) {
+            return Stability::Perfect;
         }
-        if total < 290 {
-            return "Greater";
+        let deviation = self.magimins.max_deviation(&target.magimins);
+        if deviation <= Stability::VeryStable.max_deviation() {
+            Stability::VeryStable
+        } else if deviation <= Stability::Stable.max_deviation() {
+            Stability::Stable
+        } else {
+            Stability::Unstable
         }
-        if total < 470 {
-            return "Grand";
+    }
+
+    /**
+     * This potion's stability, if it is within tolerance of the target ratio.
+     */
+    pub fn tolerated_stability(
+        &self,
+        target: &IngredientRatio,
+        max_deviation: f64,
+        min_stability: Stability,
+    ) -> Option<Stability> {
+        let stability = self.stability(target);
+        if stability < min_stability
+            || (stability != Stability::Perfect
+                && self.magimins.max_deviation(&target.magimins) > max_deviation)
+        {
+            return None;
         }
-        if total < 720 {
-            return "Superior";
+        Some(stability)
+    }
+}
+
+/**
+ * How closely a potion follows its recipe's magimin ratio, as graded by the game.
+ * Unstable potions cannot be sold.
+ */
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, ValueEnum, Serialize, Deserialize)]
+// Serialized under the same names as --min-stability takes, "very-stable" and so on.
+#[serde(rename_all = "kebab-case")]
+pub enum Stability {
+    Unstable,
+    Stable,
+    VeryStable,
+    Perfect,
+}
+
+/**
+ * Stability bands of the game's cauldron ratio map, as the largest share, in
+ * percentage points, any one magimin may stray from the recipe's share. The
+ * game draws the Very Stable ring 5 points around the target and the Stable
+ * ring 15 points around it; anything outside is Unstable, and only an exact
+ * ratio is Perfect.
+ */
+const STABLE_DEVIATION: f64 = 15.0;
+/** See STABLE_DEVIATION. */
+const VERY_STABLE_DEVIATION: f64 = 5.0;
+
+impl Stability {
+    /**
+     * Largest per-magimin deviation, in percentage points, still graded at
+     * least this stable. See STABLE_DEVIATION for the game's bands.
+     */
+    pub fn max_deviation(&self) -> f64 {
+        match self {
+            Stability::Unstable => 100.0,
+            Stability::Stable => STABLE_DEVIATION,
+            Stability::VeryStable => VERY_STABLE_DEVIATION,
+            Stability::Perfect => 0.0,
         }
-        return "Masterwork";
+    }
+}
+
+impl fmt::Display for Stability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stability::Unstable => "Unstable",
+            Stability::Stable => "Stable",
+            Stability::VeryStable => "Very Stable",
+            Stability::Perfect => "Perfect",
+        })
     }
 }
 
@@ -333,12 +540,140 @@ pub struct PotionRecipe<'a> {
     cost: usize,
 }
 
+impl<'a> PotionRecipe<'a> {
+    pub fn new(ingredients: Vec<&'a Ingredient>) -> PotionRecipe<'a> {
+        let state = ingredients
+            .iter()
+            .fold(RecipeState::default(), |state, ingredient| {
+                &state + *ingredient
+            });
+        PotionRecipe {
+            attributes: state.attributes,
+            ingredients,
+            cost: state.cost,
+        }
+    }
+
+    /**
+     * A recipe the search reached, with the ingredients it started with
+     * moved into pool order among the rest.
+     */
+    fn found(ingredients: &[&'a Ingredient], state: &RecipeState) -> PotionRecipe<'a> {
+        let mut ingredients = ingredients.to_vec();
+        ingredients.sort();
+        PotionRecipe {
+            ingredients,
+            attributes: state.attributes.clone(),
+            cost: state.cost,
+        }
+    }
+
+    /**
+     * Reads a recipe written like "3x Feyberry 2x MandrakeRoot, BogBeet"
+     * from the pool's ingredients. Names may be in any case.
+     */
+    pub fn parse(
+        pool: &'a [(Ingredient, Option<usize>)],
+        text: &str,
+    ) -> Result<PotionRecipe<'a>, String> {
+        let unreadable = |between: &str| {
+            let between = between.trim_matches(|c: char| c.is_whitespace() || c == ',');
+            (!between.is_empty()).then(|| format!("can't read \"{}\"", between))
+        };
+        let mut ingredients = Vec::new();
+        let mut magimins = 0usize;
+        let mut end = 0;
+        for captures in RECIPE_RE.captures_iter(text) {
+            let whole = captures.get(0).unwrap();
+            if let Some(e) = unreadable(&text[end..whole.start()]) {
+                return Err(e);
+            }
+            end = whole.end();
+            let count = captures.get(1).map_or(Ok(1), |count| {
+                count
+                    .as_str()
+                    .parse::<usize>()
+                    .map_err(|e| format!("{}: {}", count.as_str(), e))
+            })?;
+            let name = &captures[2];
+            let ingredient = pool
+                .iter()
+                .map(|(ingredient, _)| ingredient)
+                .find(|ingredient| ingredient.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("there's no ingredient called {}", name))?;
+            magimins = magimins.saturating_add(count.saturating_mul(ingredient.magimins.total()));
+            if count > MAX_MAGIMINS || magimins > MAX_MAGIMINS {
+                return Err(format!(
+                    "too many ingredients, a potion holds at most {} magimins",
+                    MAX_MAGIMINS
+                ));
+            }
+            ingredients.extend(std::iter::repeat_n(ingredient, count));
+        }
+        if let Some(e) = unreadable(&text[end..]) {
+            return Err(e);
+        }
+        if ingredients.is_empty() {
+            return Err("the recipe has no ingredients".to_owned());
+        }
+        // In pool order, like the recipes the search finds.
+        ingredients.sort();
+        Ok(PotionRecipe::new(ingredients))
+    }
+
+    /**
+     * Rebuilds saved recipes, given as ingredient names, from the pool's ingredients.
+     */
+    pub fn from_names(
+        pool: &'a [(Ingredient, Option<usize>)],
+        recipes: &[Vec<String>],
+    ) -> Result<Vec<PotionRecipe<'a>>, String> {
+        let by_name: HashMap<&str, &Ingredient> = pool
+            .iter()
+            .map(|(ingredient, _)| (ingredient.name.as_str(), ingredient))
+            .collect();
+        recipes
+            .iter()
+            .map(|names| {
+                let ingredients = names
+                    .iter()
+                    .map(|name| {
+                        by_name
+                            .get(name.as_str())
+                            .copied()
+                            .ok_or_else(|| format!("unknown ingredient {}", name))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(PotionRecipe::new(ingredients))
+            })
+            .collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.ingredients.iter().map(|i| i.name.clone()).collect()
+    }
+}
+
+/**
+ * What `enumerate` keeps up to date as it adds ingredients: the potion's
+ * attributes and its cost, so that no node has to look over its ingredients again.
+ */
 #[derive(Default, Debug, Clone)]
-pub struct TargetRecipe {
-    // attributes.magimins is the ideal recipe ratio
+pub struct RecipeState {
     attributes: PotionAttributes,
-    min_magimins: usize,
-    max_magimins: usize,
+    cost: usize,
+}
+
+impl ops::Add<&Ingredient> for &RecipeState {
+    type Output = RecipeState;
+
+    #[inline]
+    fn add(self, rhs: &Ingredient) -> RecipeState {
+        RecipeState {
+            attributes: &self.attributes + rhs,
+            cost: self.cost + rhs.price,
+        }
+    }
 }
 
 /**
@@ -348,28 +683,37 @@ pub struct TargetRecipe {
  * ideally targeting a specific ratio,
  * with a callback indicating whether to early abort a given recipe,
  * and a callback to accept a recipe that passes validation.
+ * The callback also gets the pool headed by the ingredient just added.
  */
 pub fn enumerate<'a, RecipeCb>(
     ingredient_pool: &'a [(Ingredient, Option<usize>)],
     max_ingredients: usize,
     // Recipe so far. Must not change any existing values. Can append new values.
     current_ingredients: &mut Vec<&'a Ingredient>,
-    mut current_ratio: PotionAttributes,
+    mut current_state: RecipeState,
     cb: &mut RecipeCb,
 ) where
-    RecipeCb: FnMut(&[&'a Ingredient], &PotionAttributes) -> bool,
+    RecipeCb: FnMut(&[&'a Ingredient], &RecipeState, &'a [(Ingredient, Option<usize>)]) -> bool,
 {
-    if ingredient_pool.len() == 0 {
+    if ingredient_pool.is_empty() {
         return;
     }
     let mandatory_ingredient = &ingredient_pool[0];
+    // The recipe may have started with all there is of it.
+    if mandatory_ingredient.1 == Some(0) {
+        return;
+    }
     let max_current_ingredient = (max_ingredients - current_ingredients.len())
         .min(mandatory_ingredient.1.unwrap_or(max_ingredients));
     assert!(max_current_ingredient > 0);
     for _ in 1..=max_current_ingredient {
         current_ingredients.push(&mandatory_ingredient.0);
-        current_ratio = &current_ratio + &mandatory_ingredient.0;
-        if !cb(current_ingredients.as_slice(), &current_ratio) {
+        current_state = &current_state + &mandatory_ingredient.0;
+        if !cb(
+            current_ingredients.as_slice(),
+            &current_state,
+            ingredient_pool,
+        ) {
             return;
         }
         if current_ingredients.len() >= max_ingredients {
@@ -383,7 +727,7 @@ pub fn enumerate<'a, RecipeCb>(
                 &ingredient_pool[ingredient_pool.len() - j..],
                 max_ingredients,
                 current_ingredients,
-                current_ratio.clone(),
+                current_state.clone(),
                 cb,
             );
             current_ingredients.truncate(current_ingredients_len);
@@ -391,7 +735,7 @@ pub fn enumerate<'a, RecipeCb>(
     }
 }
 
-#[derive(Default, Debug, Clone, Eq, Ord)]
+#[derive(Default, Debug, Clone, Eq)]
 pub struct IngredientRatio {
     magimins: Magimins,
     taste: isize,
@@ -415,14 +759,17 @@ impl PartialEq for IngredientRatio {
 impl PartialOrd for IngredientRatio {
     #[inline]
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        let mut cmp = self.max.partial_cmp(&other.max);
-        if cmp == Some(std::cmp::Ordering::Equal) {
-            cmp = self.sense_score().partial_cmp(&other.sense_score());
-        }
-        if cmp == Some(std::cmp::Ordering::Equal) {
-            cmp = other.price.partial_cmp(&self.price);
-        }
-        cmp
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IngredientRatio {
+    #[inline]
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.max
+            .cmp(&other.max)
+            .then_with(|| self.sense_score().cmp(&other.sense_score()))
+            .then_with(|| other.price.cmp(&self.price))
     }
 }
 
@@ -466,39 +813,6 @@ impl IngredientRatio {
         }
         true
     }
-
-    fn satisfying_ratio(&self, target: &IngredientRatio) -> Option<usize> {
-        if self.count == 0 {
-            return Some(0);
-        }
-        let sm = self.magimins.as_array();
-        let tm = target.magimins.as_array();
-        let mut target_ratio = 0;
-        let mut mismatch = false;
-        for i in 0..sm.len() {
-            if sm[i] == 0 && tm[i] == 0 {
-                continue;
-            }
-
-            if sm[i] != 0 && tm[i] == 0 {
-                return None;
-            }
-
-            if mismatch {
-                continue;
-            }
-
-            mismatch |= sm[i] == 0 && tm[i] != 0 || sm[i] % tm[i] > 0;
-
-            if target_ratio == 0 {
-                target_ratio = sm[i] / tm[i];
-            } else {
-                mismatch |= sm[i] / tm[i] != target_ratio;
-            }
-        }
-
-        Some(if mismatch { 0 } else { target_ratio })
-    }
 }
 
 pub fn rms(expected: [usize; 5], expected_total: usize, actual: [usize; 5]) -> f64 {
@@ -514,7 +828,7 @@ pub fn rms(expected: [usize; 5], expected_total: usize, actual: [usize; 5]) -> f
     if expected_total != actual_total {
         sum_squares += (expected_total.abs_diff(actual_total)).pow(2) as f64;
     }
-    return sum_squares.sqrt();
+    sum_squares.sqrt()
 }
 
 pub fn print(
@@ -522,6 +836,7 @@ pub fn print(
     count: usize,
     magimins: usize,
     sense: isize,
+    stability: Stability,
     price: usize,
     ingredients: &[&Ingredient],
 ) {
@@ -540,23 +855,26 @@ pub fn print(
     compact_names.push(format!("{}x {}", c, curr_name.name));
 
     println!(
-        "{}{} ingredients, {} magimins, {} sense score, ${}\n\t{}",
+        "{}{} ingredients, {} magimins, {} sense score, {}, ${}\n\t{}",
         prefix,
         count,
         magimins,
         sense,
+        stability,
         price,
         compact_names.join("\n\t")
     );
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, ValueEnum, Serialize, Deserialize)]
 pub enum SolveAlgorithm {
     EXACT,
     APPROXIMATE,
+    ANNEAL,
+    GENETIC,
 }
 
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, ValueEnum, Serialize, Deserialize)]
 pub enum Recipe {
     HEALTH,
     MANA,
@@ -659,318 +977,1337 @@ impl Recipe {
             }
         }
 
-        Magimins { a, b, c, d, e }
+        Magimins::new(a, b, c, d, e)
+    }
+
+    /**
+     * What the cauldron has to hold for this recipe.
+     */
+    pub fn target(&self) -> IngredientRatio {
+        IngredientRatio {
+            magimins: self.to_magimins(),
+
+            taste: 0,
+            feel: 0,
+            sight: 0,
+            smell: 0,
+            sound: 0,
+
+            count: 10,
+            min: 290,
+            max: 575,
+            price: 0,
+        }
     }
 }
 
-#[derive(Debug, Parser)]
+/**
+ * How many ingredients and magimins the cauldron holds, and the fewest magimins
+ * to report, for the commands that change them from the recipe's.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Cauldron {
+    pub count: usize,
+    pub max: usize,
+    pub min: usize,
+}
+
+impl Cauldron {
+    pub fn new(recipe: Recipe) -> Cauldron {
+        let target = recipe.target();
+        Cauldron {
+            count: target.count,
+            max: target.max,
+            min: target.min,
+        }
+    }
+
+    pub fn target(&self, recipe: Recipe) -> IngredientRatio {
+        let mut target = recipe.target();
+        target.count = self.count;
+        target.max = self.max;
+        target.min = self.min;
+        target
+    }
+
+    /**
+     * Whether a search can fill this cauldron.
+     */
+    pub fn check(&self) -> Result<(), String> {
+        if self.count == 0 {
+            return Err("the cauldron has to hold an ingredient".to_owned());
+        }
+        if self.max > MAX_MAGIMINS {
+            return Err(format!(
+                "{} magimins is too many, a potion holds at most {}",
+                self.max, MAX_MAGIMINS
+            ));
+        }
+        if self.min > self.max {
+            return Err(format!(
+                "at least {} magimins is more than the {} the cauldron holds",
+                self.min, self.max
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Parser)]
 pub struct Args {
-    #[arg(short, long, value_name="ingredients.txt", default_value_t=("ingredients.rs".to_owned()))]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        short,
+        long,
+        global = true,
+        value_name = "ingredients.txt",
+        default_value = "ingredients.rs"
+    )]
     ingredients: String,
 
-    #[arg(short, long, value_enum, value_name="mode", default_value_t=SolveAlgorithm::EXACT)]
+    #[arg(short, long, global=true, value_enum, value_name="mode", default_value_t=SolveAlgorithm::EXACT)]
     mode: SolveAlgorithm,
 
-    #[arg(short, long, value_enum, value_name="recipe", default_value_t=Recipe::HEALTH)]
+    #[arg(short, long, global=true, value_enum, value_name="recipe", default_value_t=Recipe::HEALTH)]
     recipe: Recipe,
+
+    /// Largest per-magimin deviation from the target ratio accepted in approximate mode.
+    #[arg(long, global=true, value_name="percent", default_value_t=5.0, value_parser=utils::parse_percent)]
+    max_deviation: f64,
+
+    /// Only report potions at least this stable.
+    #[arg(long, global=true, value_enum, value_name="stability", default_value_t=Stability::Stable)]
+    min_stability: Stability,
+
+    /// Only report recipes costing at most this much.
+    #[arg(long, global = true, value_name = "price")]
+    max_cost: Option<usize>,
+
+    /// Seed for the stochastic solvers.
+    #[arg(long, global = true, value_name = "seed", default_value_t = 0)]
+    seed: u64,
+
+    /// Steps per annealing restart.
+    #[arg(long, global = true, value_name = "count", default_value_t = 1_000_000)]
+    iterations: usize,
+
+    /// Independent annealing runs, each from a different random recipe.
+    #[arg(long, global = true, value_name = "count", default_value_t = 8)]
+    restarts: usize,
+
+    /// Recipes per generation of the genetic solver.
+    #[arg(long, global = true, value_name = "count", default_value_t = 200)]
+    population: usize,
+
+    /// Generations the genetic solver breeds.
+    #[arg(long, global = true, value_name = "count", default_value_t = 500)]
+    generations: usize,
+
+    /// Chance that a newly bred recipe has one ingredient swapped.
+    #[arg(long, global=true, value_name="chance", default_value_t=0.3, value_parser=utils::parse_chance)]
+    mutation_rate: f64,
+
+    /// Stop searching after this long, e.g. 30s or 5m.
+    #[arg(long, global=true, value_name="duration", value_parser=utils::parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Search every ingredient, even those another one is at least as good as.
+    #[arg(long, global = true)]
+    keep_dominated: bool,
+
+    /// Only search recipes that start with these ingredients, e.g. "2x SphinxFlea, 1x Puckberry".
+    #[arg(long, global = true, value_name = "ingredients")]
+    start_with: Option<String>,
+
+    /// Save the progress of an exact or approximate search to this file now and then.
+    #[arg(long, global = true, value_name = "checkpoint.json")]
+    checkpoint: Option<PathBuf>,
+
+    /// How often to save the checkpoint.
+    #[arg(long, global=true, value_name="duration", default_value="60s", value_parser=utils::parse_duration)]
+    checkpoint_interval: Duration,
+
+    /// Continue the search saved in this checkpoint, saving new checkpoints to it too.
+    #[arg(long, global = true, value_name = "checkpoint.json")]
+    resume: Option<PathBuf>,
+
+    /// Search even if the results of the same search are cached, and don't cache them.
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Where finished searches are cached.
+    #[arg(long, global = true, value_name = "dir", default_value = "cache")]
+    cache_dir: PathBuf,
+
+    /// How to write the recipes found.
+    #[arg(long, global = true, value_enum, value_name = "format", default_value_t = output::Format::Text)]
+    output: output::Format,
+
+    /// Only log errors.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more details, twice for even more.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Worker threads for the search, defaults to one per CPU.
+    #[arg(long, global = true, value_name = "count", default_value_t = default_threads())]
+    threads: usize,
+
+    /// Objective weight of the ratio error (rms).
+    #[arg(long, global = true, value_name = "weight", default_value_t = 1.0)]
+    weight_error: f64,
+
+    /// Objective weight of the recipe's cost.
+    #[arg(long, global = true, value_name = "weight", default_value_t = 0.0)]
+    weight_cost: f64,
+
+    /// Objective weight of the sense score, higher prefers better senses.
+    #[arg(long, global = true, value_name = "weight", default_value_t = 0.0)]
+    weight_sense: f64,
 }
 
-#[derive(Clone)]
-pub struct SolveState<'a> {
-    shared_state: Arc<SharedState<'a>>,
-    specific_state: SpecificState,
+impl Args {
+    /**
+     * The deviation approximate mode accepts, no more than --min-stability allows.
+     */
+    fn accepted_deviation(&self) -> f64 {
+        self.max_deviation.min(self.min_stability.max_deviation())
+    }
+
+    fn tolerance(&self) -> objective::Tolerance {
+        objective::Tolerance {
+            max_deviation: self.accepted_deviation(),
+            min_stability: self.min_stability,
+            max_cost: self.max_cost,
+        }
+    }
+
+    fn objective(&self) -> objective::Objective {
+        objective::Objective {
+            error: self.weight_error,
+            cost: self.weight_cost,
+            sense: self.weight_sense,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Count the recipes a search would visit and time a short sample of it.
+    Estimate {
+        /// How long to sample the search for.
+        #[arg(long, value_name="duration", default_value="2s", value_parser=utils::parse_duration)]
+        sample: Duration,
+    },
+    /// Measure how many recipes per second an exact or approximate search visits.
+    Bench {
+        /// How long to search for.
+        #[arg(long, value_name="duration", default_value="10s", value_parser=utils::parse_duration)]
+        duration: Duration,
+    },
+    /// Work out the potion a recipe such as "3x Feyberry 2x MandrakeRoot 1x BogBeet" makes.
+    Eval {
+        /// The ingredients in the cauldron, each after its count.
+        #[arg(id = "recipe_ingredients", value_name = "ingredients")]
+        ingredients: String,
+    },
+    /// Load the catalog once, then search and change the settings as often as asked.
+    Repl,
+    /// Search in a full-screen terminal UI, changing the settings as it goes.
+    Tui,
+    /// Rank the ingredients that could go in next, after those given with --start-with.
+    Suggest {
+        /// What makes one ingredient a better pick than another.
+        #[arg(long, value_enum, value_name = "rank", default_value_t = suggest::Rank::Completions)]
+        by: suggest::Rank,
+    },
+    /// Hand an exact or approximate search out to worker processes.
+    Coordinate {
+        /// Address to wait for workers on.
+        #[arg(long, value_name = "address", default_value = "127.0.0.1:7878")]
+        listen: String,
+        /// Start this many workers on this machine, one thread each.
+        #[arg(long, value_name = "count", default_value_t = 0)]
+        spawn_local: usize,
+    },
+    /// Search for a coordinator, with --threads threads.
+    Work {
+        /// Address of the coordinator.
+        #[arg(long, value_name = "address")]
+        connect: String,
+    },
+    /// Delete cached results.
+    CachePrune {
+        /// Only delete results cached longer ago than this, e.g. 7d.
+        #[arg(long, value_name="duration", value_parser=utils::parse_duration)]
+        older_than: Option<Duration>,
+    },
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
 }
 
 pub struct SharedState<'a> {
-    ingredients: Vec<(Ingredient, Option<usize>)>,
+    ingredients: &'a [(Ingredient, Option<usize>)],
     target: IngredientRatio,
     acc: Mutex<Vec<PotionRecipe<'a>>>,
+    // Most a recipe may cost.
+    max_cost: Option<usize>,
+    // Where the ingredients every recipe starts with are in the pool.
+    start_with: Vec<usize>,
+    // Set on Ctrl-C or timeout; workers stop as soon as they notice.
+    stop: Arc<AtomicBool>,
+    progress: Arc<progress::Progress>,
 }
 
 #[derive(Clone)]
 pub enum SpecificState {
     Exact,
     Approximate {
-        global_best_rms: Arc<atomic_float::AtomicF64>,
+        // In percentage points, per magimin.
+        max_deviation: f64,
+        min_stability: Stability,
     },
 }
 
-#[tokio::main]
-async fn main() {
-    let args = Args::parse();
+impl SpecificState {
+    pub fn new(
+        mode: SolveAlgorithm,
+        max_deviation: f64,
+        min_stability: Stability,
+    ) -> SpecificState {
+        match mode {
+            SolveAlgorithm::APPROXIMATE => SpecificState::Approximate {
+                max_deviation,
+                min_stability,
+            },
+            _ => SpecificState::Exact,
+        }
+    }
+}
+
+/**
+ * How an exhaustive search runs, apart from what it looks for.
+ */
+pub struct SearchOptions {
+    threads: usize,
+    // The subtrees to search, all of them unless resuming.
+    roots: Vec<parallel::Subtree>,
+    checkpoint: Option<checkpoint::Writer>,
+}
+
+/**
+ * The check `enumerate` runs on every recipe in an exhaustive search: prunes
+ * what can't lead anywhere and collects the recipes that pass into `found`.
+ */
+#[allow(clippy::type_complexity)]
+fn exhaustive_callback<'a: 's, 's>(
+    shared_state: &'s SharedState<'a>,
+    specific_state: &'s SpecificState,
+    space: &'s progress::SearchSpace,
+    reach: &'s bounds::Reach,
+    found: &'s Mutex<Vec<PotionRecipe<'a>>>,
+) -> impl FnMut(
+    &[&'a Ingredient],
+    &RecipeState,
+    &'a [(Ingredient, Option<usize>)],
+    &mut progress::Counters,
+) -> bool
+       + 's {
+    let target = &shared_state.target;
+    let seeded = shared_state.start_with.len();
+    move |candidate_ingredients: &[&Ingredient],
+          candidate_state: &RecipeState,
+          pool: &[(Ingredient, Option<usize>)],
+          counters: &mut progress::Counters|
+          -> bool {
+        // Return false to tell the enumerator to abort this recipe.
+        // First do some common checks that are algorithm agnostic.
+        assert!(!candidate_ingredients.is_empty());
+        let candidate_ratio = &candidate_state.attributes;
+        let candidate_total = candidate_ratio.magimins.total();
+        if candidate_total > target.max {
+            counters.over_max += 1;
+            counters.skipped += space.descendants(pool.len(), candidate_ingredients, seeded);
+            return false;
+        }
+
+        let last_ingredient_magimins = candidate_ingredients.last().unwrap().mutamin;
+        let remaining_ingredients_count = target.count - candidate_ingredients.len();
+        if (candidate_total + (last_ingredient_magimins * remaining_ingredients_count)) < target.min
+        {
+            counters.below_min += 1;
+            counters.skipped += space.descendants(pool.len(), candidate_ingredients, seeded);
+            return false;
+        }
 
-    let mut target = IngredientRatio {
-        magimins: args.recipe.to_magimins(),
+        // Ingredients never get cheaper.
+        if shared_state
+            .max_cost
+            .is_some_and(|max_cost| candidate_state.cost > max_cost)
+        {
+            counters.over_budget += 1;
+            counters.skipped += space.descendants(pool.len(), candidate_ingredients, seeded);
+            return false;
+        }
+
+        // Algorithm specific checks.
+        match *specific_state {
+            SpecificState::Exact => {
+                if reach.exact_unreachable(
+                    target,
+                    &candidate_ratio.magimins,
+                    pool.len(),
+                    remaining_ingredients_count,
+                ) {
+                    let skipped = space.descendants(pool.len(), candidate_ingredients, seeded);
+                    counters.unreachable += 1;
+                    counters.unreachable_skipped += skipped;
+                    counters.skipped += skipped;
+                    return false;
+                }
+
+                if candidate_total < target.min {
+                    return true;
+                }
+                match candidate_ratio.satisfying_ratio(target) {
+                    None => {
+                        return true;
+                    }
+                    Some(0) => {
+                        return true;
+                    }
+                    Some(_) => {}
+                };
+                found
+                    .lock()
+                    .unwrap()
+                    .push(PotionRecipe::found(candidate_ingredients, candidate_state));
+            }
+            SpecificState::Approximate {
+                max_deviation,
+                min_stability,
+            } => {
+                // Magimins never go away, so the final share of each magimin is at
+                // least what we have now spread over the largest allowed total.
+                let candidate_ratio_magimins_array = candidate_ratio.magimins.as_array();
+                let target_magimins_array = target.magimins.as_array();
+                let target_magimins_total = target.magimins.total() as f64;
+                for i in 0..candidate_ratio_magimins_array.len() {
+                    let least_share = candidate_ratio_magimins_array[i] as f64 / target.max as f64;
+                    let target_share = target_magimins_array[i] as f64 / target_magimins_total;
+                    if 100.0 * (least_share - target_share) > max_deviation + 1e-9 {
+                        counters.ratio_mismatch += 1;
+                        counters.skipped +=
+                            space.descendants(pool.len(), candidate_ingredients, seeded);
+                        return false;
+                    }
+                }
+
+                if reach.share_unreachable(
+                    target,
+                    &candidate_ratio.magimins,
+                    pool.len(),
+                    remaining_ingredients_count,
+                    max_deviation,
+                ) {
+                    let skipped = space.descendants(pool.len(), candidate_ingredients, seeded);
+                    counters.unreachable += 1;
+                    counters.unreachable_skipped += skipped;
+                    counters.skipped += skipped;
+                    return false;
+                }
+
+                if candidate_total < target.min {
+                    return true;
+                }
+
+                if candidate_ratio
+                    .tolerated_stability(target, max_deviation, min_stability)
+                    .is_none()
+                {
+                    return true;
+                }
+
+                found
+                    .lock()
+                    .unwrap()
+                    .push(PotionRecipe::found(candidate_ingredients, candidate_state));
+            }
+        }
+        true
+    }
+}
+
+/**
+ * Enumerates every recipe in the given subtrees,
+ * collecting the ones that pass validation into the shared state.
+ * Returns whether it searched them all rather than being stopped.
+ */
+fn solve_exhaustive(
+    shared_state: &SharedState,
+    specific_state: &SpecificState,
+    options: SearchOptions,
+) -> bool {
+    let space = progress::SearchSpace::new(shared_state.ingredients, shared_state.target.count);
+    // Whatever isn't left to search when resuming was covered before.
+    let left: u64 = options
+        .roots
+        .iter()
+        .map(|subtree| subtree.size(&space))
+        .sum();
+    shared_state.progress.flush(&mut progress::Counters {
+        skipped: space.total() - left,
+        ..Default::default()
+    });
+    let finished = Arc::new(AtomicBool::new(false));
+    let reporter = progress::spawn_reporter(
+        shared_state.progress.clone(),
+        space.total(),
+        finished.clone(),
+    );
+
+    let target = &shared_state.target;
+    let space = &space;
+    let reach = &bounds::Reach::new(shared_state.ingredients, target.count);
+    let save = |frontier: &[parallel::Subtree]| {
+        if let Some(writer) = &options.checkpoint {
+            match writer.write(shared_state.ingredients, frontier, &shared_state.acc) {
+                Ok(recipes) => log::debug!(
+                    "Saved {} recipes and {} subtrees left to {}",
+                    recipes,
+                    frontier.len(),
+                    writer.path.display()
+                ),
+                Err(e) => log::warn!("Couldn't write the checkpoint: {}", e),
+            }
+        }
+    };
+    let search = parallel::Search {
+        threads: options.threads,
+        space,
+        progress: &shared_state.progress,
+        stop: &shared_state.stop,
+        checkpoint: options
+            .checkpoint
+            .as_ref()
+            .map(|writer| (writer.interval, &save as parallel::SaveFrontier)),
+    };
+    let frontier = search.run(
+        shared_state.ingredients,
+        target.count,
+        options.roots.clone(),
+        || {
+            exhaustive_callback(
+                shared_state,
+                specific_state,
+                space,
+                reach,
+                &shared_state.acc,
+            )
+        },
+    );
+
+    finished.store(true, Ordering::Relaxed);
+    reporter.join().unwrap();
+
+    // Saved first, as the checkpoint counts the recipes it saved in the order they were found.
+    save(&frontier);
+    // Subtrees in progress at a checkpoint are searched again after resuming.
+    if left < space.total() {
+        let mut recipes = shared_state.acc.lock().unwrap();
+        recipes.sort_by(|a, b| a.ingredients.cmp(&b.ingredients));
+        recipes.dedup_by(|a, b| a.ingredients == b.ingredients);
+    }
+    frontier.is_empty()
+}
+
+/**
+ * Runs the search `args` asks for on this machine.
+ * Returns whether it ran to the end rather than being stopped.
+ */
+fn solve(args: &Args, shared_state: &SharedState, options: SearchOptions) -> bool {
+    match args.mode {
+        SolveAlgorithm::EXACT | SolveAlgorithm::APPROXIMATE => solve_exhaustive(
+            shared_state,
+            &SpecificState::new(args.mode, args.accepted_deviation(), args.min_stability),
+            options,
+        ),
+        SolveAlgorithm::ANNEAL => anneal::solve(
+            shared_state,
+            anneal::Settings {
+                objective: args.objective(),
+                iterations: args.iterations,
+                restarts: args.restarts,
+                seed: args.seed,
+                tolerance: args.tolerance(),
+            },
+            options.threads,
+        ),
+        SolveAlgorithm::GENETIC => genetic::solve(
+            shared_state,
+            genetic::Settings {
+                objective: args.objective(),
+                population: args.population,
+                generations: args.generations,
+                mutation_rate: args.mutation_rate,
+                seed: args.seed,
+                tolerance: args.tolerance(),
+            },
+        ),
+    }
+}
+
+/**
+ * Prints every recipe found, best first. Recipes that only differ by
+ * interchangeable ingredients are printed once, listing the substitutions.
+ */
+fn report(
+    shared_state: &SharedState,
+    objective: &objective::Objective,
+    substitutions: &dominance::Substitutions,
+    results: &output::Writer,
+    complete: bool,
+) {
+    let target = &shared_state.target;
+    let mut recipes = shared_state.acc.lock().unwrap();
+    objective.rank(target, &mut recipes);
+
+    results.header();
+    let mut seen = HashSet::new();
+    let mut distinct = 0;
+    for recipe in recipes.iter() {
+        if !seen.insert(substitutions.key(&recipe.ingredients)) {
+            continue;
+        }
+        distinct += 1;
+        results.recipe(target, recipe, substitutions.used(&recipe.ingredients));
+    }
 
-        taste: 0,
-        feel: 0,
-        sight: 0,
-        smell: 0,
-        sound: 0,
+    results.summary(&output::Summary {
+        recipes: distinct,
+        counting_interchangeable: recipes.len(),
+        complete,
+    });
+}
+
+/**
+ * Searches for `duration`, or until done, and prints how fast it went.
+ */
+fn bench(
+    shared_state: &SharedState,
+    specific_state: &SpecificState,
+    threads: usize,
+    duration: Duration,
+) {
+    let stop = shared_state.stop.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(duration);
+        stop.store(true, Ordering::Relaxed);
+    });
+    let start = Instant::now();
+    solve_exhaustive(
+        shared_state,
+        specific_state,
+        SearchOptions {
+            threads,
+            roots: parallel::Subtree::roots(
+                shared_state.ingredients.len(),
+                &shared_state.start_with,
+            ),
+            checkpoint: None,
+        },
+    );
+    let elapsed = start.elapsed().as_secs_f64();
+    let nodes = shared_state.progress.nodes();
+    println!(
+        "Visited {} recipes in {:.1}s, {:.0} recipes/s with --threads {}",
+        nodes,
+        elapsed,
+        nodes as f64 / elapsed,
+        threads
+    );
+}
+
+/**
+ * Prints how many recipes an unpruned search enumerates, then spends `sample`
+ * timing the search and probing random paths through it, to guess how long the
+ * whole search takes with pruning and how sure that guess is.
+ */
+fn estimate(
+    shared_state: &SharedState,
+    specific_state: &SpecificState,
+    mode: SolveAlgorithm,
+    threads: usize,
+    sample: Duration,
+) {
+    let target = &shared_state.target;
+    let space = progress::SearchSpace::new(shared_state.ingredients, target.count);
+    let roots = parallel::Subtree::roots(shared_state.ingredients.len(), &shared_state.start_with);
+    let size: u64 = roots.iter().map(|subtree| subtree.size(&space)).sum();
+    println!(
+        "{} ingredients, up to {} per recipe: {} recipes to enumerate without pruning",
+        shared_state.ingredients.len(),
+        target.count,
+        size
+    );
+    if let SolveAlgorithm::ANNEAL | SolveAlgorithm::GENETIC = mode {
+        println!(
+            "The {:?} solver only samples this space, its runtime depends on its settings",
+            mode
+        );
+        return;
+    }
+
+    // Half the sample times a stretch of the search, for how fast nodes are visited.
+    let stop = shared_state.stop.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(sample / 2);
+        stop.store(true, Ordering::Relaxed);
+    });
+    let start = Instant::now();
+    let complete = solve_exhaustive(
+        shared_state,
+        specific_state,
+        SearchOptions {
+            threads,
+            roots,
+            checkpoint: None,
+        },
+    );
+    let elapsed = start.elapsed().as_secs_f64();
+    if complete {
+        println!(
+            "The whole search finished within the sample, in {}",
+            utils::format_duration(elapsed)
+        );
+        return;
+    }
+    let visited = shared_state.progress.nodes();
+    let rate = visited as f64 / elapsed;
+
+    // The other half probes random paths down the tree, for how many nodes there are.
+    let deadline = Instant::now() + sample / 2;
+    let reach = &bounds::Reach::new(shared_state.ingredients, target.count);
+    let (n, sum, sum_squares) = std::thread::scope(|scope| {
+        let probers: Vec<_> = (0..threads.max(1))
+            .map(|thread| {
+                let space = &space;
+                scope.spawn(move || {
+                    let mut rng = ChaCha8Rng::seed_from_u64(thread as u64);
+                    let found = Mutex::new(Vec::new());
+                    let mut counters = progress::Counters::default();
+                    let mut cb =
+                        exhaustive_callback(shared_state, specific_state, space, reach, &found);
+                    let (mut n, mut sum, mut sum_squares) = (0u64, 0.0, 0.0);
+                    while Instant::now() < deadline {
+                        let nodes = parallel::probe(
+                            shared_state.ingredients,
+                            target.count,
+                            &shared_state.start_with,
+                            space,
+                            &mut |ingredients, state, pool| {
+                                cb(ingredients, state, pool, &mut counters)
+                            },
+                            &mut rng,
+                        );
+                        found.lock().unwrap().clear();
+                        n += 1;
+                        sum += nodes;
+                        sum_squares += nodes * nodes;
+                    }
+                    (n, sum, sum_squares)
+                })
+            })
+            .collect();
+        probers
+            .into_iter()
+            .map(|prober| prober.join().unwrap())
+            .fold(
+                (0, 0.0, 0.0),
+                |(n, sum, sum_squares), (pn, psum, psquares)| {
+                    (n + pn, sum + psum, sum_squares + psquares)
+                },
+            )
+    });
+    if n < 2 || rate <= 0.0 {
+        println!("Couldn't sample enough of the search, try a longer --sample");
+        return;
+    }
+    let mean = sum / n as f64;
+    let error = ((sum_squares / n as f64 - mean * mean).max(0.0) / (n - 1) as f64).sqrt();
+    // Within about two standard errors 95% of the time, and never fewer nodes
+    // than the sample already visited.
+    let visited = visited as f64;
+    let (low, high) = (
+        (mean - 1.96 * error).max(visited),
+        (mean + 1.96 * error).max(visited),
+    );
+    let mean = mean.max(visited);
+    println!(
+        "About {:.0} nodes to visit (95% between {:.0} and {:.0}, from {} random probes), \
+         at {:.0} nodes/s",
+        mean, low, high, n, rate
+    );
+    println!(
+        "The whole search should take about {} (95% between {} and {})",
+        utils::format_duration(mean / rate),
+        utils::format_duration(low / rate),
+        utils::format_duration(high / rate)
+    );
+    if error > mean / 4.0 {
+        println!("The probes disagree a lot, a longer --sample narrows the range");
+    }
+}
+
+/**
+ * Prints the potion a hand-written recipe makes, graded against the target recipe.
+ */
+fn eval(
+    catalog: &[(Ingredient, Option<usize>)],
+    target: &IngredientRatio,
+    recipe: Recipe,
+    ingredients: &str,
+) -> Result<(), String> {
+    let potion = PotionRecipe::parse(catalog, ingredients)?;
+    let attributes = &potion.attributes;
+    let counts = output::counts(&potion.ingredients);
+    for (ingredient, count) in counts.iter() {
+        let available = catalog
+            .iter()
+            .find(|(other, _)| std::ptr::eq(other, *ingredient))
+            .and_then(|(_, available)| *available);
+        if available.is_some_and(|available| available < *count) {
+            log::warn!(
+                "Only {} {} available, the recipe uses {}",
+                available.unwrap(),
+                ingredient.name,
+                count
+            );
+        }
+    }
+    if potion.ingredients.len() > target.count {
+        log::warn!(
+            "The cauldron only holds {} ingredients, the recipe uses {}",
+            target.count,
+            potion.ingredients.len()
+        );
+    }
+    if attributes.magimins.total() > target.max {
+        log::warn!(
+            "The cauldron only holds {} magimins, the recipe has {}",
+            target.max,
+            attributes.magimins.total()
+        );
+    }
+
+    let names: Vec<String> = counts
+        .iter()
+        .map(|(ingredient, count)| format!("{}x {}", count, ingredient.name))
+        .collect();
+    println!("{}", names.join(", "));
+    println!(
+        "{} ingredients, {} magimins ({}): {} {}, {} star{}",
+        potion.ingredients.len(),
+        attributes.magimins.total(),
+        attributes.magimins,
+        attributes.tier(),
+        format!("{:?}", recipe).to_lowercase(),
+        attributes.stars(),
+        if attributes.stars() == 1 { "" } else { "s" }
+    );
+    println!(
+        "Senses: taste {:+}, feel {:+}, sight {:+}, smell {:+}, sound {:+}, sense score {}",
+        attributes.taste,
+        attributes.feel,
+        attributes.sight,
+        attributes.smell,
+        attributes.sound,
+        attributes.sense_score()
+    );
+    println!("Cost: ${}", potion.cost);
+    match attributes.satisfying_ratio(target) {
+        Some(multiple @ 1..) => println!("Ratio: exactly {}x the recipe's ratio", multiple),
+        ratio => println!(
+            "Ratio: off the recipe's ratio by up to {:.1}%{}",
+            attributes.magimins.max_deviation(&target.magimins),
+            if ratio.is_none() {
+                ", with magimins the recipe doesn't use"
+            } else {
+                ""
+            }
+        ),
+    }
+    println!("Stability: {}", attributes.stability(target));
+    Ok(())
+}
 
-        count: 10,
-        min: 290,
-        max: 575,
-        price: 0,
+/**
+ * The mode and those of its settings that change what it finds, for the result cache.
+ */
+fn mode_key(args: &Args, max_deviation: f64, start_with: &[String]) -> String {
+    let tolerance = format!(
+        "max_deviation {} min_stability {:?} max_cost {:?}",
+        max_deviation, args.min_stability, args.max_cost
+    );
+    let objective = format!(
+        "weights {} {} {}",
+        args.weight_error, args.weight_cost, args.weight_sense
+    );
+    let key = match args.mode {
+        SolveAlgorithm::EXACT => format!("exact max_cost {:?}", args.max_cost),
+        SolveAlgorithm::APPROXIMATE => format!("approximate {}", tolerance),
+        SolveAlgorithm::ANNEAL => format!(
+            "anneal {} {} iterations {} restarts {} seed {}",
+            tolerance, objective, args.iterations, args.restarts, args.seed
+        ),
+        SolveAlgorithm::GENETIC => format!(
+            "genetic {} {} population {} generations {} mutation_rate {} seed {}",
+            tolerance, objective, args.population, args.generations, args.mutation_rate, args.seed
+        ),
     };
+    if start_with.is_empty() {
+        key
+    } else {
+        format!("{} start_with {}", key, start_with.join(" "))
+    }
+}
 
-    let mut ingredients = Ingredient::load(&args.ingredients);
+/**
+ * Narrows the ingredients down to those worth searching for the target,
+ * in search order, never pruning those in `start_with`. Also returns the
+ * ingredients merged by the pre-filter.
+ */
+pub fn candidates(
+    mut ingredients: Vec<(Ingredient, Option<usize>)>,
+    target: &IngredientRatio,
+    mode: SolveAlgorithm,
+    keep_dominated: bool,
+    start_with: &[String],
+) -> (
+    Vec<(Ingredient, Option<usize>)>,
+    Vec<dominance::Equivalence>,
+) {
     let old_len = ingredients.len();
-    ingredients.retain(|(i, _)| match args.mode {
+    ingredients.retain(|(i, _)| match mode {
         SolveAlgorithm::EXACT => target.is_possible_ingredient(i),
-        SolveAlgorithm::APPROXIMATE => true,
+        SolveAlgorithm::APPROXIMATE | SolveAlgorithm::ANNEAL | SolveAlgorithm::GENETIC => true,
     });
     ingredients.sort();
 
-    println!(
+    log::info!(
         "Found {} ingredients, only {} are candidates",
         old_len,
         ingredients.len()
     );
 
-    if let SolveAlgorithm::APPROXIMATE = args.mode {
-        target.magimins = target.max / &target.magimins;
+    let mut equivalences = Vec::new();
+    if !keep_dominated {
+        let (kept, prefilter) = dominance::prefilter(ingredients, target.count, start_with);
+        prefilter.log();
+        ingredients = kept;
+        equivalences = prefilter.equivalences;
+        log::info!("Searching {} ingredients", ingredients.len());
+    }
+    (ingredients, equivalences)
+}
+
+/**
+ * Takes the ingredients every recipe starts with out of what's available,
+ * returning where they are in the pool. Ingredients merged by the pre-filter
+ * are taken from the one they were merged into.
+ */
+pub fn seed(
+    pool: &mut [(Ingredient, Option<usize>)],
+    equivalences: &[dominance::Equivalence],
+    target: &IngredientRatio,
+    names: &[String],
+) -> Result<Vec<usize>, String> {
+    let mut prefix = Vec::with_capacity(names.len());
+    let mut state = RecipeState::default();
+    for name in names.iter() {
+        let searched = equivalences
+            .iter()
+            .find(|equivalence| equivalence.members.contains(name))
+            .map_or(name, |equivalence| &equivalence.representative);
+        let i = pool
+            .iter()
+            .position(|(ingredient, _)| &ingredient.name == searched)
+            .ok_or_else(|| format!("{} can't be in the recipe", name))?;
+        match &mut pool[i].1 {
+            Some(0) => {
+                let used = prefix.iter().filter(|&&j| j == i).count();
+                return Err(format!("only {} {} available", used, name));
+            }
+            Some(available) => *available -= 1,
+            None => {}
+        }
+        state = &state + &pool[i].0;
+        prefix.push(i);
+    }
+    if prefix.len() >= target.count {
+        return Err(format!(
+            "that's {} ingredients, leaving no room in a cauldron of {}",
+            prefix.len(),
+            target.count
+        ));
+    }
+    if state.attributes.magimins.total() > target.max {
+        return Err(format!(
+            "that's {} magimins, more than the {} the cauldron holds",
+            state.attributes.magimins.total(),
+            target.max
+        ));
+    }
+    prefix.sort();
+    Ok(prefix)
+}
+
+/**
+ * The ingredients worth searching for a target, starting with those given with --start-with.
+ */
+pub struct Pool {
+    pub ingredients: Vec<(Ingredient, Option<usize>)>,
+    // Merged into the ingredients searched by the pre-filter.
+    pub equivalences: Vec<dominance::Equivalence>,
+    // The ingredients every recipe starts with, one name per ingredient.
+    pub start_with: Vec<String>,
+    // And where they are in the pool.
+    pub prefix: Vec<usize>,
+}
+
+impl Pool {
+    pub fn new(
+        catalog: Vec<(Ingredient, Option<usize>)>,
+        target: &IngredientRatio,
+        args: &Args,
+    ) -> Result<Pool, String> {
+        // Checked against the whole catalog first, so that a typo isn't
+        // mistaken for an ingredient left out of the search.
+        let start_with = match &args.start_with {
+            Some(_) if matches!(args.mode, SolveAlgorithm::ANNEAL | SolveAlgorithm::GENETIC) => {
+                return Err(
+                    "Only exact and approximate searches can start with given ingredients"
+                        .to_owned(),
+                );
+            }
+            Some(text) => PotionRecipe::parse(&catalog, text)
+                .map_err(|e| format!("Can't start with {}: {}", text, e))?
+                .names(),
+            None => Vec::new(),
+        };
+        let (mut ingredients, equivalences) =
+            candidates(catalog, target, args.mode, args.keep_dominated, &start_with);
+        // Magimins add up in 16-bit lanes, so no cauldron the search can fill may overflow one.
+        if let Some((heaviest, _)) = ingredients.iter().max_by_key(|(i, _)| i.magimins.total()) {
+            if target.count.saturating_mul(heaviest.magimins.total()) > MAX_MAGIMINS {
+                return Err(format!(
+                    "{} {} would hold more than the {} magimins a potion can",
+                    target.count, heaviest.name, MAX_MAGIMINS
+                ));
+            }
+        }
+        let prefix = seed(&mut ingredients, &equivalences, target, &start_with).map_err(|e| {
+            format!(
+                "Can't start with {}: {}",
+                args.start_with.as_deref().unwrap_or_default(),
+                e
+            )
+        })?;
+        Ok(Pool {
+            ingredients,
+            equivalences,
+            start_with,
+            prefix,
+        })
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    logging::init(args.quiet, args.verbose);
+
+    if let Some(Command::CachePrune { older_than }) = args.command {
+        let cache = cache::Cache {
+            dir: args.cache_dir.clone(),
+        };
+        match cache.prune(older_than) {
+            Ok((count, bytes)) => println!(
+                "Deleted {} cached results, {} bytes, from {}",
+                count,
+                bytes,
+                cache.dir.display()
+            ),
+            Err(e) => {
+                log::error!("Couldn't prune the cache: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(Command::Work { connect }) = &args.command {
+        if let Err(e) = distributed::work(connect, args.threads) {
+            log::error!("Worker stopped: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let target = args.recipe.target();
+    let catalog = Ingredient::load(&args.ingredients);
+    if let Some(Command::Eval { ingredients }) = &args.command {
+        if let Err(e) = eval(&catalog, &target, args.recipe, ingredients) {
+            log::error!("Can't evaluate the recipe: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Repl) = args.command {
+        repl::run(args, catalog);
+        return;
+    }
+    if let Some(Command::Tui) = args.command {
+        if let Err(e) = tui::run(args, catalog) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
     }
-    let shared_state = Arc::new(SharedState {
+    let results = output::Writer::new(
+        args.output,
+        catalog.iter().map(|(i, _)| i.name.clone()).collect(),
+    );
+    let Pool {
         ingredients,
+        equivalences,
+        start_with,
+        prefix,
+    } = match Pool::new(catalog, &target, &args) {
+        Ok(pool) => pool,
+        Err(e) => {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let substitutions = dominance::Substitutions::new(&ingredients, &equivalences);
+
+    let shared_state = SharedState {
+        ingredients: &ingredients,
         target,
         acc: Mutex::new(Vec::new()),
-    });
-    let shared_specific_state = match args.mode {
-        SolveAlgorithm::APPROXIMATE => SpecificState::Approximate {
-            global_best_rms: Arc::new(atomic_float::AtomicF64::new(0.0)),
-        },
-        _ => SpecificState::Exact,
+        max_cost: args.max_cost,
+        start_with: prefix,
+        stop: Arc::new(AtomicBool::new(false)),
+        progress: Arc::new(progress::Progress::default()),
     };
 
-    let mut futs = Vec::new();
-
-    for i in 0..shared_state.ingredients.len() {
-        let shared_state = shared_state.clone();
-        let specific_state = shared_specific_state.clone();
-        futs.push(Box::pin(tokio::spawn(async move {
-            let shared_state = shared_state;
-            let specific_state = specific_state;
-            let mut thread_best_recipes = Vec::new();
-            let mut ingredients_vec = Vec::new();
-            let target = &shared_state.target;
-            ingredients_vec.reserve_exact(target.max);
-            println!("starting from the top");
-            enumerate(
-                &(shared_state.ingredients[i..]),
-                target.count,
-                &mut ingredients_vec,
-                PotionAttributes::default(),
-                &mut |candidate_ingredients: &[&Ingredient],
-                      candidate_ratio: &PotionAttributes|
-                 -> bool {
-                    // Return false to tell the enumerator to abort this recipe.
-                    // First do some common checks that are algorithm agnostic.
-                    assert!(candidate_ingredients.len() > 0);
-                    let candidate_total = candidate_ratio.magimins.total();
-                    if candidate_total > target.max {
-                        return false;
-                    }
-
-                    let last_ingredient_magimins = candidate_ingredients.last().unwrap().mutamin;
-                    let remaining_ingredients_count = target.count - candidate_ingredients.len();
-                    if (candidate_total + (last_ingredient_magimins * remaining_ingredients_count))
-                        < target.min
-                    {
-                        return false;
-                    }
+    // The first Ctrl-C asks every worker to stop and report what it has, a second one exits.
+    signal_hook::flag::register_conditional_shutdown(
+        signal_hook::consts::SIGINT,
+        1,
+        shared_state.stop.clone(),
+    )
+    .unwrap();
+    signal_hook::flag::register(signal_hook::consts::SIGINT, shared_state.stop.clone()).unwrap();
+
+    let max_deviation = args.accepted_deviation();
+    let objective = args.objective();
+    let specific_state = SpecificState::new(args.mode, max_deviation, args.min_stability);
+
+    if let Some(Command::Estimate { sample }) = args.command {
+        estimate(
+            &shared_state,
+            &specific_state,
+            args.mode,
+            args.threads,
+            sample,
+        );
+        return;
+    }
+    if let Some(Command::Bench { duration }) = args.command {
+        bench(&shared_state, &specific_state, args.threads, duration);
+        return;
+    }
 
-                    if candidate_total < target.min {
-                        return true;
-                    }
+    let approximate = matches!(args.mode, SolveAlgorithm::APPROXIMATE);
+    let key = checkpoint::SearchKey {
+        ingredients: args.ingredients.clone(),
+        ingredients_hash: utils::input_hash(&args.ingredients),
+        recipe: format!("{:?}", args.recipe),
+        magimins: shared_state.target.magimins.as_array(),
+        count: shared_state.target.count,
+        min: shared_state.target.min,
+        max: shared_state.target.max,
+        mode: format!("{:?}", args.mode),
+        max_deviation: approximate.then_some(max_deviation),
+        min_stability: approximate.then(|| format!("{:?}", args.min_stability)),
+        keep_dominated: args.keep_dominated,
+        max_cost: args.max_cost,
+        start_with: start_with.clone(),
+    };
+    let mut roots = parallel::Subtree::roots(ingredients.len(), &shared_state.start_with);
+    let mut resumed_saved = None;
+    if let Some(path) = &args.resume {
+        let resumed = checkpoint::Checkpoint::load(path)
+            .and_then(|checkpoint| checkpoint.check(&key).map(|_| checkpoint))
+            .and_then(|checkpoint| {
+                let recipes = checkpoint.recipes(&ingredients)?;
+                Ok((checkpoint, recipes))
+            });
+        match resumed {
+            Ok((checkpoint, recipes)) => {
+                log::info!(
+                    "Resuming from {}: {} recipes found, {} subtrees left",
+                    path.display(),
+                    recipes.len(),
+                    checkpoint.frontier.len()
+                );
+                shared_state.acc.lock().unwrap().extend(recipes);
+                roots = checkpoint.frontier;
+                resumed_saved = Some(checkpoint.saved);
+            }
+            Err(e) => {
+                log::error!("Can't resume: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let writer = args
+        .checkpoint
+        .clone()
+        .or(args.resume.clone())
+        .map(|path| match resumed_saved {
+            // Written somewhere else, the new checkpoint saves the resumed recipes too.
+            Some(saved) if args.resume.as_ref() == Some(&path) => {
+                checkpoint::Writer::resume(path, args.checkpoint_interval, key, saved)
+            }
+            _ => checkpoint::Writer::new(path, args.checkpoint_interval, key),
+        });
+    if writer.is_some() && matches!(args.mode, SolveAlgorithm::ANNEAL | SolveAlgorithm::GENETIC) {
+        log::error!("Only exact and approximate searches can be checkpointed");
+        std::process::exit(1);
+    }
+    if let Some(Command::Coordinate { .. }) = args.command {
+        if writer.is_some() {
+            log::error!("Distributed searches can't be checkpointed");
+            std::process::exit(1);
+        }
+        if matches!(args.mode, SolveAlgorithm::ANNEAL | SolveAlgorithm::GENETIC) {
+            log::error!("Only exact and approximate searches can be distributed");
+            std::process::exit(1);
+        }
+    }
+    if let Some(Command::Suggest { .. }) = args.command {
+        if matches!(args.mode, SolveAlgorithm::ANNEAL | SolveAlgorithm::GENETIC) {
+            log::error!("Only exact and approximate searches can suggest ingredients");
+            std::process::exit(1);
+        }
+    }
 
-                    // Algorithm specific checks.
-                    match &specific_state {
-                        SpecificState::Exact => {
-                            match candidate_ratio.satisfying_ratio(&target) {
-                                None => {
-                                    return true;
-                                }
-                                Some(0) => {
-                                    return true;
-                                }
-                                Some(_) => {}
-                            };
-                            let potion_price = candidate_ingredients
-                                .iter()
-                                .fold(0, |p, ingredient| p + ingredient.price);
-                            print(
-                                "++ ",
-                                candidate_ingredients.len(),
-                                candidate_total,
-                                candidate_ratio.sense_score(),
-                                potion_price,
-                                candidate_ingredients,
-                            );
-                            shared_state.acc.lock().unwrap().push(PotionRecipe {
-                                ingredients: candidate_ingredients.to_vec(),
-                                attributes: candidate_ratio.clone(),
-                                cost: potion_price,
-                            });
-                        }
-                        &SpecificState::Approximate {
-                            ref global_best_rms,
-                        } => {
-                            let scaled_expected_ratio_array = target.magimins.as_array();
-
-                            let candidate_ratio_magimins_array =
-                                candidate_ratio.magimins.as_array();
-                            let new_rms = rms(
-                                scaled_expected_ratio_array,
-                                target.max,
-                                candidate_ratio_magimins_array,
-                            );
-                            // TODO flag to control if bigger is always better.
-                            if thread_best_recipes.is_empty() {
-                                let potion_price = candidate_ingredients
-                                    .iter()
-                                    .fold(0, |p, ingredient| p + ingredient.price);
-                                print(
-                                    "++ ",
-                                    candidate_ingredients.len(),
-                                    candidate_ratio.magimins.total(),
-                                    candidate_ratio.sense_score(),
-                                    potion_price,
-                                    candidate_ingredients,
-                                );
-                                thread_best_recipes.push(PotionRecipe {
-                                    ingredients: candidate_ingredients.to_vec(),
-                                    attributes: candidate_ratio.clone(),
-                                    cost: potion_price,
-                                });
-                                return true;
-                            }
-
-                            // If the candidate recipe is at least as good as a recipe we've already found
-                            if thread_best_recipes.last().unwrap().ingredients.len()
-                                <= candidate_ingredients.len()
-                            {
-                                let current_rms = global_best_rms.load(atomic::Ordering::Acquire);
-                                // But if it's got a worse error than the current global best.
-                                if new_rms > current_rms {
-                                    // Then project if we can possibly beat the global best with what we've got.
-                                    let mut potential_ratio_magimins_array =
-                                        candidate_ratio_magimins_array;
-                                    let mut useful_deltas: [usize; 5] = [0; 5];
-                                    for i in 0..scaled_expected_ratio_array.len() {
-                                        if scaled_expected_ratio_array[i] != 0 {
-                                            if scaled_expected_ratio_array[i]
-                                                > candidate_ratio_magimins_array[i]
-                                            {
-                                                useful_deltas[i] = scaled_expected_ratio_array[i]
-                                                    .abs_diff(candidate_ratio_magimins_array[i]);
-                                            }
-                                        }
-                                    }
-
-                                    let mut magimins_remaining = target.max - candidate_total;
-
-                                    loop {
-                                        //println!("magimins remaining: {}", magimins_remaining);
-                                        assert!(magimins_remaining < target.max);
-                                        if magimins_remaining == 0 {
-                                            break;
-                                        }
-
-                                        let mut delta_indices = [false; 5];
-                                        let mut delta_indices_count = 0;
-                                        let mut largest_delta = 0;
-                                        let mut next_delta = 0;
-                                        for i in 0..useful_deltas.len() {
-                                            let delta = useful_deltas[i];
-                                            if delta > largest_delta {
-                                                next_delta = largest_delta;
-                                                largest_delta = delta;
-                                                delta_indices = [false; 5];
-                                                delta_indices[i] = true;
-                                                delta_indices_count = 1;
-                                            } else if delta == largest_delta {
-                                                delta_indices[i] = true;
-                                                delta_indices_count += 1;
-                                            } else if delta > next_delta {
-                                                next_delta = useful_deltas[i];
-                                            }
-                                        }
-                                        //println!("delta indices: {}", delta_indices_count);
-                                        if delta_indices_count == 0 {
-                                            break;
-                                        }
-                                        //println!("largest delta: {}, next delta: {}", largest_delta, next_delta);
-                                        let computed_delta = largest_delta - next_delta;
-                                        let needed_magimins = computed_delta * delta_indices_count;
-                                        //println!("needed magimins: {}, magimins remaining: {}", needed_magimins, magimins_remaining);
-                                        if needed_magimins <= magimins_remaining {
-                                            // Blindly add the delta to every index needing it.
-                                            for i in 0..useful_deltas.len() {
-                                                if delta_indices[i] {
-                                                    potential_ratio_magimins_array[i] +=
-                                                        computed_delta;
-                                                    useful_deltas[i] -= computed_delta;
-                                                }
-                                            }
-                                            magimins_remaining -= needed_magimins;
-                                        } else {
-                                            // Need to distribute the remainder equally.
-                                            let available_delta =
-                                                magimins_remaining / delta_indices_count;
-                                            magimins_remaining -=
-                                                available_delta * delta_indices_count;
-                                            for i in 0..useful_deltas.len() {
-                                                if delta_indices[i] {
-                                                    potential_ratio_magimins_array[i] +=
-                                                        available_delta;
-
-                                                    useful_deltas[i] -= available_delta;
-                                                    if magimins_remaining > 0 {
-                                                        potential_ratio_magimins_array[i] += 1;
-                                                        magimins_remaining -= 1;
-                                                        useful_deltas[i] -= 1;
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                    let potential_rms = rms(
-                                        scaled_expected_ratio_array,
-                                        target.max,
-                                        potential_ratio_magimins_array,
-                                    );
-                                    return potential_rms < current_rms;
-                                }
-                                println!(
-                                    "total: {}, {}, error: {}",
-                                    candidate_ratio.magimins.total(),
-                                    candidate_ratio.magimins,
-                                    new_rms,
-                                );
-                                let potion_price = candidate_ingredients
-                                    .iter()
-                                    .fold(0, |p, ingredient| p + ingredient.price);
-                                print(
-                                    "++ ",
-                                    candidate_ingredients.len(),
-                                    candidate_ratio.magimins.total(),
-                                    candidate_ratio.sense_score(),
-                                    potion_price,
-                                    candidate_ingredients,
-                                );
-                                shared_state.acc.lock().unwrap().push(PotionRecipe {
-                                    ingredients: candidate_ingredients.to_vec(),
-                                    attributes: candidate_ratio.clone(),
-                                    cost: potion_price,
-                                });
-                            }
+    let cache = cache::Cache {
+        dir: args.cache_dir.clone(),
+    };
+    let cache_key = cache::key(
+        &ingredients,
+        &shared_state.target,
+        &mode_key(&args, max_deviation, &start_with),
+    );
+    // A checkpointed search runs even when cached, so that resumed recipes
+    // aren't added twice and the checkpoint gets finished.
+    let cached = if args.no_cache || writer.is_some() {
+        None
+    } else {
+        cache.load(&cache_key, &ingredients)
+    };
+    let complete = match cached {
+        Some(recipes) => {
+            log::info!(
+                "Using the results cached in {}",
+                cache.path(&cache_key).display()
+            );
+            shared_state.acc.lock().unwrap().extend(recipes);
+            true
+        }
+        None => {
+            // Only started now, so that loading a checkpoint doesn't use up the time.
+            let _timer = utils::Timer::start(args.timeout, &shared_state.stop);
+            // Told by the search itself, so that a --timeout going off
+            // after it finished doesn't count as stopping it.
+            let complete = match &args.command {
+                Some(Command::Coordinate {
+                    listen,
+                    spawn_local,
+                }) => {
+                    let setup = distributed::Setup {
+                        ingredients: utils::read_input(&args.ingredients),
+                        recipe: args.recipe,
+                        mode: args.mode,
+                        max_deviation,
+                        min_stability: args.min_stability,
+                        max_cost: args.max_cost,
+                        keep_dominated: args.keep_dominated,
+                        start_with: start_with.clone(),
+                    };
+                    let listener = std::net::TcpListener::bind(listen)
+                        .map_err(|e| format!("{}: {}", listen, e));
+                    match listener.and_then(|listener| {
+                        distributed::coordinate(
+                            &shared_state,
+                            &specific_state,
+                            &setup,
+                            listener,
+                            *spawn_local,
+                        )
+                    }) {
+                        Ok(complete) => complete,
+                        Err(e) => {
+                            log::error!("Can't coordinate the search: {}", e);
+                            std::process::exit(1);
                         }
                     }
-                    return true;
-                },
-            );
-            ingredients_vec.clear();
-        })));
+                }
+                _ => solve(
+                    &args,
+                    &shared_state,
+                    SearchOptions {
+                        threads: args.threads,
+                        roots,
+                        checkpoint: writer,
+                    },
+                ),
+            };
+
+            // Only complete results are worth serving again.
+            if !args.no_cache && complete {
+                match cache.store(&cache_key, &shared_state.acc.lock().unwrap()) {
+                    Ok(()) => {
+                        log::debug!("Cached the results in {}", cache.path(&cache_key).display())
+                    }
+                    Err(e) => log::warn!("Couldn't cache the results: {}", e),
+                }
+            }
+            complete
+        }
+    };
+
+    if let Some(Command::Suggest { by }) = args.command {
+        if !complete {
+            log::warn!("Search stopped early, the suggestions only count the recipes found so far");
+        }
+        results.suggestions(&suggest::suggest(&shared_state, by));
+        return;
     }
+    report(
+        &shared_state,
+        &objective,
+        &substitutions,
+        &results,
+        complete,
+    );
 }
@@ -0,0 +1,213 @@
+use crate::{rms, Ingredient, IngredientRatio, PotionAttributes, PotionRecipe, Stability};
+use rand::Rng;
+
+// Added to the score per magimin a recipe falls short of the target minimum.
+const SHORTFALL_PENALTY: f64 = 10.0;
+
+/**
+ * What the stochastic solvers minimize, and how results are ranked:
+ * a weighted sum of the ratio error, the ingredient cost and the negated sense score.
+ */
+#[derive(Clone, Debug)]
+pub struct Objective {
+    pub error: f64,
+    pub cost: f64,
+    pub sense: f64,
+}
+
+impl Objective {
+    pub fn score(
+        &self,
+        target: &IngredientRatio,
+        attributes: &PotionAttributes,
+        cost: usize,
+    ) -> f64 {
+        let error = rms(
+            (target.max / &target.magimins).as_array(),
+            target.max,
+            attributes.magimins.as_array(),
+        );
+        let shortfall = target.min.saturating_sub(attributes.magimins.total());
+        self.error * error + self.cost * cost as f64 - self.sense * attributes.sense_score() as f64
+            + SHORTFALL_PENALTY * shortfall as f64
+    }
+
+    /**
+     * Sorts recipes best first, breaking ties by sense score, cost, then ingredients.
+     */
+    pub fn rank(&self, target: &IngredientRatio, recipes: &mut [PotionRecipe]) {
+        recipes.sort_by(|a, b| {
+            self.score(target, &a.attributes, a.cost)
+                .total_cmp(&self.score(target, &b.attributes, b.cost))
+                .then_with(|| b.attributes.sense_score().cmp(&a.attributes.sense_score()))
+                .then_with(|| a.cost.cmp(&b.cost))
+                .then_with(|| a.ingredients.cmp(&b.ingredients))
+        });
+    }
+}
+
+/**
+ * Which recipes the stochastic solvers may report, same as approximate mode.
+ */
+#[derive(Clone, Debug)]
+pub struct Tolerance {
+    pub max_deviation: f64,
+    pub min_stability: Stability,
+    pub max_cost: Option<usize>,
+}
+
+impl Tolerance {
+    pub fn accepts(&self, target: &IngredientRatio, candidate: &Candidate) -> Option<Stability> {
+        let total = candidate.attributes.magimins.total();
+        if total < target.min || total > target.max {
+            return None;
+        }
+        if self
+            .max_cost
+            .is_some_and(|max_cost| candidate.cost > max_cost)
+        {
+            return None;
+        }
+        candidate
+            .attributes
+            .tolerated_stability(target, self.max_deviation, self.min_stability)
+    }
+}
+
+/**
+ * A recipe as a count of each ingredient in the pool, with its attributes
+ * and cost kept up to date as ingredients are added and removed.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Candidate {
+    pub counts: Vec<usize>,
+    pub len: usize,
+    pub attributes: PotionAttributes,
+    pub cost: usize,
+}
+
+impl Candidate {
+    pub fn empty(pool_len: usize) -> Candidate {
+        Candidate {
+            counts: vec![0; pool_len],
+            ..Default::default()
+        }
+    }
+
+    pub fn from_counts(pool: &[(Ingredient, Option<usize>)], counts: Vec<usize>) -> Candidate {
+        let mut candidate = Candidate::empty(pool.len());
+        for (i, &count) in counts.iter().enumerate() {
+            for _ in 0..count {
+                candidate.add(pool, i);
+            }
+        }
+        candidate
+    }
+
+    /**
+     * Fills an empty cauldron with random ingredients, skipping any that don't fit.
+     */
+    pub fn random<R: Rng>(
+        pool: &[(Ingredient, Option<usize>)],
+        target: &IngredientRatio,
+        rng: &mut R,
+    ) -> Candidate {
+        let mut candidate = Candidate::empty(pool.len());
+        for _ in 0..target.count {
+            let i = rng.gen_range(0..pool.len());
+            if candidate.can_add(pool, target, i) {
+                candidate.add(pool, i);
+            }
+        }
+        candidate
+    }
+
+    pub fn can_add(
+        &self,
+        pool: &[(Ingredient, Option<usize>)],
+        target: &IngredientRatio,
+        i: usize,
+    ) -> bool {
+        let (ingredient, available) = &pool[i];
+        self.len < target.count
+            && self.counts[i] < available.unwrap_or(target.count)
+            && self.attributes.magimins.total() + ingredient.mutamin <= target.max
+    }
+
+    pub fn add(&mut self, pool: &[(Ingredient, Option<usize>)], i: usize) {
+        self.counts[i] += 1;
+        self.len += 1;
+        self.attributes = &self.attributes + &pool[i].0;
+        self.cost += pool[i].0.price;
+    }
+
+    pub fn remove(&mut self, pool: &[(Ingredient, Option<usize>)], i: usize) {
+        assert!(self.counts[i] > 0);
+        self.counts[i] -= 1;
+        self.len -= 1;
+        self.attributes = &self.attributes - &pool[i].0;
+        self.cost -= pool[i].0.price;
+    }
+
+    /**
+     * Picks one of the ingredients in the cauldron, weighted by how many of it there are.
+     */
+    pub fn random_ingredient<R: Rng>(&self, rng: &mut R) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut n = rng.gen_range(0..self.len);
+        for (i, &count) in self.counts.iter().enumerate() {
+            if n < count {
+                return Some(i);
+            }
+            n -= count;
+        }
+        unreachable!()
+    }
+
+    /**
+     * Swaps one ingredient in the cauldron for a random other one.
+     * Returns false, leaving the candidate as it was, if the swap didn't fit.
+     */
+    pub fn swap<R: Rng>(
+        &mut self,
+        pool: &[(Ingredient, Option<usize>)],
+        target: &IngredientRatio,
+        rng: &mut R,
+    ) -> bool {
+        let i = match self.random_ingredient(rng) {
+            Some(i) => i,
+            None => return false,
+        };
+        let j = rng.gen_range(0..pool.len());
+        self.remove(pool, i);
+        if i == j || !self.can_add(pool, target, j) {
+            self.add(pool, i);
+            return false;
+        }
+        self.add(pool, j);
+        true
+    }
+
+    pub fn recipe<'a>(&self, pool: &'a [(Ingredient, Option<usize>)]) -> PotionRecipe<'a> {
+        PotionRecipe {
+            ingredients: self.ingredients(pool),
+            attributes: self.attributes.clone(),
+            cost: self.cost,
+        }
+    }
+
+    /**
+     * The recipe as a list of ingredients in pool order, ready for `print`.
+     */
+    pub fn ingredients<'a>(&self, pool: &'a [(Ingredient, Option<usize>)]) -> Vec<&'a Ingredient> {
+        let mut ingredients = Vec::with_capacity(self.len);
+        for (i, &count) in self.counts.iter().enumerate() {
+            for _ in 0..count {
+                ingredients.push(&pool[i].0);
+            }
+        }
+        ingredients
+    }
+}
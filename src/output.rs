@@ -0,0 +1,456 @@
+use crate::suggest::Suggestion;
+use crate::{print, rms, Ingredient, IngredientRatio, PotionRecipe, Stability};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/**
+ * How the results are written to stdout.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    // One JSON object per line: a record per recipe, then a summary.
+    Json,
+    // A header, then a row per recipe with a count column per ingredient.
+    Csv,
+    // What each ingredient adds to each magimin, and how far off the ratio that is.
+    Table,
+}
+
+// The CSV columns after the ingredient counts.
+const COLUMNS: [&str; 17] = [
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "total",
+    "tier",
+    "stability",
+    "taste",
+    "feel",
+    "sight",
+    "smell",
+    "sound",
+    "sense_score",
+    "cost",
+    "error",
+    "interchangeable",
+];
+
+/**
+ * Writes the results in one format.
+ */
+pub struct Writer {
+    format: Format,
+    // Every ingredient in the catalog, in file order, so that the CSV
+    // columns don't depend on which ingredients were searched.
+    catalog: Vec<String>,
+    column: HashMap<String, usize>,
+}
+
+/**
+ * How many recipes were found, written after them.
+ */
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    // Recipes that only differ by interchangeable ingredients count once.
+    pub recipes: usize,
+    pub counting_interchangeable: usize,
+    // False if the search was stopped before it finished.
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Magimins {
+    a: usize,
+    b: usize,
+    c: usize,
+    d: usize,
+    e: usize,
+    total: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Senses {
+    taste: isize,
+    feel: isize,
+    sight: isize,
+    smell: isize,
+    sound: isize,
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'r> {
+    ingredients: BTreeMap<&'r str, usize>,
+    magimins: Magimins,
+    tier: &'static str,
+    stability: Stability,
+    senses: Senses,
+    sense_score: isize,
+    cost: usize,
+    // How far an imperfect recipe is off the target ratio.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deviation: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    interchangeable: Vec<&'r [String]>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Line<'r> {
+    Recipe(Record<'r>),
+    Summary(&'r Summary),
+    Suggestion(&'r Suggestion),
+}
+
+/**
+ * How many of each ingredient the recipe uses, in recipe order.
+ */
+pub fn counts<'r>(ingredients: &[&'r Ingredient]) -> Vec<(&'r Ingredient, usize)> {
+    let mut counts: Vec<(&Ingredient, usize)> = Vec::new();
+    for &ingredient in ingredients.iter() {
+        match counts.last_mut() {
+            Some((last, count)) if std::ptr::eq(*last, ingredient) => *count += 1,
+            _ => counts.push((ingredient, 1)),
+        }
+    }
+    counts
+}
+
+fn error(target: &IngredientRatio, recipe: &PotionRecipe) -> f64 {
+    rms(
+        (target.max / &target.magimins).as_array(),
+        target.max,
+        recipe.attributes.magimins.as_array(),
+    )
+}
+
+/**
+ * Lays a recipe out as a table of what each ingredient adds to each magimin,
+ * followed by the magimin shares against the target ratio.
+ * Returns the headline and the rows.
+ */
+pub fn table(target: &IngredientRatio, recipe: &PotionRecipe) -> (String, Vec<String>) {
+    let attributes = &recipe.attributes;
+    let counts = counts(&recipe.ingredients);
+    let width = counts
+        .iter()
+        .map(|(ingredient, _)| ingredient.name.len())
+        .chain(["Ingredient".len(), "Deviation".len()])
+        .max()
+        .unwrap();
+    let row = |label: &str, count: &str, cells: [String; 5]| {
+        format!(
+            "{:<width$} {:>5} {:>7} {:>7} {:>7} {:>7} {:>7}",
+            label,
+            count,
+            cells[0],
+            cells[1],
+            cells[2],
+            cells[3],
+            cells[4],
+            width = width
+        )
+    };
+
+    let headline = format!(
+        "{} ingredients, {} magimins, {}, {} sense score, {}, ${}",
+        recipe.ingredients.len(),
+        attributes.magimins.total(),
+        attributes.tier(),
+        attributes.sense_score(),
+        attributes.stability(target),
+        recipe.cost
+    );
+    let mut rows = vec![row(
+        "Ingredient",
+        "Count",
+        ["A", "B", "C", "D", "E"].map(str::to_owned),
+    )];
+    for (ingredient, count) in counts.iter() {
+        let magimins = ingredient.magimins.as_array();
+        rows.push(row(
+            &ingredient.name,
+            &count.to_string(),
+            magimins.map(|magimin| (count * magimin).to_string()),
+        ));
+    }
+    let magimins = attributes.magimins.as_array();
+    let total = attributes.magimins.total().max(1) as f64;
+    let ratio = target.magimins.as_array();
+    let ratio_total = target.magimins.total() as f64;
+    let share: [f64; 5] = std::array::from_fn(|i| 100.0 * magimins[i] as f64 / total);
+    let target_share: [f64; 5] = std::array::from_fn(|i| 100.0 * ratio[i] as f64 / ratio_total);
+    rows.push(row(
+        "Total",
+        &recipe.ingredients.len().to_string(),
+        magimins.map(|magimin| magimin.to_string()),
+    ));
+    rows.push(row(
+        "Share",
+        "",
+        share.map(|share| format!("{:.1}%", share)),
+    ));
+    rows.push(row(
+        "Target",
+        "",
+        target_share.map(|share| format!("{:.1}%", share)),
+    ));
+    rows.push(row(
+        "Deviation",
+        "",
+        std::array::from_fn(|i| format!("{:+.1}", share[i] - target_share[i])),
+    ));
+    (headline, rows)
+}
+
+/**
+ * Quotes a CSV field if it needs it.
+ */
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+impl Writer {
+    pub fn new(format: Format, catalog: Vec<String>) -> Writer {
+        let column = catalog
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), i))
+            .collect();
+        Writer {
+            format,
+            catalog,
+            column,
+        }
+    }
+
+    /**
+     * Writes what comes before the recipes, if anything.
+     */
+    pub fn header(&self) {
+        if self.format == Format::Csv {
+            let mut columns: Vec<String> =
+                self.catalog.iter().map(|name| csv_field(name)).collect();
+            columns.extend(COLUMNS.map(str::to_owned));
+            println!("{}", columns.join(","));
+        }
+    }
+
+    /**
+     * Writes one recipe, with the groups of interchangeable ingredients it uses.
+     */
+    pub fn recipe(
+        &self,
+        target: &IngredientRatio,
+        recipe: &PotionRecipe,
+        interchangeable: Vec<&[String]>,
+    ) {
+        let attributes = &recipe.attributes;
+        let stability = attributes.stability(target);
+        match self.format {
+            Format::Text => {
+                if stability != Stability::Perfect {
+                    log::debug!(
+                        "total: {}, {}, error: {:.1}, deviation: {:.1}%",
+                        attributes.magimins.total(),
+                        attributes.magimins,
+                        error(target, recipe),
+                        attributes.magimins.max_deviation(&target.magimins),
+                    );
+                }
+                print(
+                    "++ ",
+                    recipe.ingredients.len(),
+                    attributes.magimins.total(),
+                    attributes.sense_score(),
+                    stability,
+                    recipe.cost,
+                    &recipe.ingredients,
+                );
+                for class in interchangeable {
+                    println!("\tinterchangeable: {}", class.join(", "));
+                }
+            }
+            Format::Json => {
+                let [a, b, c, d, e] = attributes.magimins.as_array();
+                let imperfect = stability != Stability::Perfect;
+                let record = Record {
+                    ingredients: counts(&recipe.ingredients)
+                        .into_iter()
+                        .map(|(ingredient, count)| (ingredient.name.as_str(), count))
+                        .collect(),
+                    magimins: Magimins {
+                        a,
+                        b,
+                        c,
+                        d,
+                        e,
+                        total: attributes.magimins.total(),
+                    },
+                    tier: attributes.tier(),
+                    stability,
+                    senses: Senses {
+                        taste: attributes.taste,
+                        feel: attributes.feel,
+                        sight: attributes.sight,
+                        smell: attributes.smell,
+                        sound: attributes.sound,
+                    },
+                    sense_score: attributes.sense_score(),
+                    cost: recipe.cost,
+                    error: imperfect.then(|| error(target, recipe)),
+                    deviation: imperfect
+                        .then(|| attributes.magimins.max_deviation(&target.magimins)),
+                    interchangeable,
+                };
+                println!("{}", serde_json::to_string(&Line::Recipe(record)).unwrap());
+            }
+            Format::Table => {
+                let (headline, rows) = table(target, recipe);
+                println!("++ {}", headline);
+                for row in rows.iter() {
+                    println!("\t{}", row);
+                }
+                for class in interchangeable {
+                    println!("\tinterchangeable: {}", class.join(", "));
+                }
+            }
+            Format::Csv => {
+                let mut used = vec![0; self.catalog.len()];
+                for (ingredient, count) in counts(&recipe.ingredients) {
+                    used[self.column[&ingredient.name]] += count;
+                }
+                let mut row: Vec<String> = used.iter().map(|count| count.to_string()).collect();
+                row.extend(
+                    attributes
+                        .magimins
+                        .as_array()
+                        .iter()
+                        .map(|magimin| magimin.to_string()),
+                );
+                row.push(attributes.magimins.total().to_string());
+                row.push(attributes.tier().to_owned());
+                row.push(stability.to_possible_value().unwrap().get_name().to_owned());
+                row.extend(
+                    [
+                        attributes.taste,
+                        attributes.feel,
+                        attributes.sight,
+                        attributes.smell,
+                        attributes.sound,
+                        attributes.sense_score(),
+                    ]
+                    .iter()
+                    .map(|sense| sense.to_string()),
+                );
+                row.push(recipe.cost.to_string());
+                row.push(if stability == Stability::Perfect {
+                    String::new()
+                } else {
+                    error(target, recipe).to_string()
+                });
+                let classes: Vec<String> = interchangeable
+                    .iter()
+                    .map(|class| class.join(" = "))
+                    .collect();
+                row.push(csv_field(&classes.join("; ")));
+                println!("{}", row.join(","));
+            }
+        }
+    }
+
+    pub fn summary(&self, summary: &Summary) {
+        let found = if summary.recipes == summary.counting_interchangeable {
+            format!("{} recipes", summary.recipes)
+        } else {
+            format!(
+                "{} recipes ({} counting interchangeable ingredients)",
+                summary.recipes, summary.counting_interchangeable
+            )
+        };
+        let text = if summary.complete {
+            format!("Found {}", found)
+        } else {
+            format!(
+                "Search stopped early: found {} so far, the results are incomplete",
+                found
+            )
+        };
+        match self.format {
+            Format::Text | Format::Table => println!("{}", text),
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string(&Line::Summary(summary)).unwrap()
+            ),
+            // A summary row would get in the way of loading the table.
+            Format::Csv => log::info!("{}", text),
+        }
+    }
+
+    /**
+     * Writes the ingredients that could go in next, best first.
+     */
+    pub fn suggestions(&self, suggestions: &[Suggestion]) {
+        match self.format {
+            Format::Text | Format::Table => {
+                if suggestions.is_empty() {
+                    println!("No ingredient leads to a recipe from here");
+                    return;
+                }
+                let width = suggestions
+                    .iter()
+                    .map(|suggestion| suggestion.ingredient.len())
+                    .chain(["Next ingredient".len()])
+                    .max()
+                    .unwrap();
+                println!(
+                    "{:<width$} {:>11} {:>8} {:>6}",
+                    "Next ingredient",
+                    "Completions",
+                    "Cheapest",
+                    "Error",
+                    width = width
+                );
+                for suggestion in suggestions.iter() {
+                    println!(
+                        "{:<width$} {:>11} {:>8} {:>6.1}",
+                        suggestion.ingredient,
+                        suggestion.completions,
+                        format!("${}", suggestion.cheapest),
+                        suggestion.error,
+                        width = width
+                    );
+                }
+            }
+            Format::Json => {
+                for suggestion in suggestions.iter() {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&Line::Suggestion(suggestion)).unwrap()
+                    );
+                }
+            }
+            Format::Csv => {
+                println!("ingredient,completions,cheapest,error");
+                for suggestion in suggestions.iter() {
+                    println!(
+                        "{},{},{},{}",
+                        csv_field(&suggestion.ingredient),
+                        suggestion.completions,
+                        suggestion.cheapest,
+                        suggestion.error
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use crate::progress::{Counters, Progress, SearchSpace};
+use crate::{enumerate, Ingredient, RecipeState};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Subtrees bigger than 1 / (threads * SPLIT_FACTOR) of the search are split
+// into their children before being searched.
+const SPLIT_FACTOR: u64 = 256;
+
+/**
+ * A piece of the search: every recipe `enumerate` visits from
+ * pool[start..] on top of a fixed prefix of ingredients.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subtree {
+    pub start: usize,
+    // Indices into the pool.
+    pub prefix: Vec<usize>,
+}
+
+impl Subtree {
+    /**
+     * One subtree per starting ingredient on top of the ingredients every
+     * recipe starts with, which together cover the whole search.
+     */
+    pub fn roots(pool_len: usize, prefix: &[usize]) -> Vec<Subtree> {
+        (0..pool_len)
+            .map(|start| Subtree {
+                start,
+                prefix: prefix.to_vec(),
+            })
+            .collect()
+    }
+
+    /**
+     * The nodes an unpruned search of this subtree visits.
+     */
+    pub fn size(&self, space: &SearchSpace) -> u64 {
+        space.subtree(self.start, self.prefix.len())
+    }
+
+    fn prefix_state<'a>(
+        &self,
+        pool: &'a [(Ingredient, Option<usize>)],
+    ) -> (Vec<&'a Ingredient>, RecipeState) {
+        let mut ingredients = Vec::with_capacity(pool.len());
+        let mut state = RecipeState::default();
+        for &i in self.prefix.iter() {
+            ingredients.push(&pool[i].0);
+            state = &state + &pool[i].0;
+        }
+        (ingredients, state)
+    }
+
+    /**
+     * Visits the nodes that only add the head ingredient, like `enumerate` does,
+     * and returns the subtrees below them instead of searching those.
+     */
+    pub fn split<'a, C>(
+        &self,
+        pool: &'a [(Ingredient, Option<usize>)],
+        count: usize,
+        cb: &mut C,
+    ) -> Vec<Subtree>
+    where
+        C: FnMut(&[&'a Ingredient], &RecipeState, &'a [(Ingredient, Option<usize>)]) -> bool,
+    {
+        let (mut ingredients, mut state) = self.prefix_state(pool);
+        let mut prefix = self.prefix.clone();
+        let (head, available) = &pool[self.start];
+        let mut children = Vec::new();
+        for _ in 1..=(count - prefix.len()).min(available.unwrap_or(count)) {
+            ingredients.push(head);
+            state = &state + head;
+            prefix.push(self.start);
+            if !cb(&ingredients, &state, &pool[self.start..]) {
+                break;
+            }
+            if ingredients.len() >= count {
+                break;
+            }
+            for start in (self.start + 1..pool.len()).rev() {
+                children.push(Subtree {
+                    start,
+                    prefix: prefix.clone(),
+                });
+            }
+        }
+        children
+    }
+
+    /**
+     * Visits every node in the subtree.
+     */
+    pub fn search<'a, C>(&self, pool: &'a [(Ingredient, Option<usize>)], count: usize, cb: &mut C)
+    where
+        C: FnMut(&[&'a Ingredient], &RecipeState, &'a [(Ingredient, Option<usize>)]) -> bool,
+    {
+        let (mut ingredients, state) = self.prefix_state(pool);
+        enumerate(&pool[self.start..], count, &mut ingredients, state, cb);
+    }
+}
+
+/**
+ * Walks one random path down the search tree below `prefix`, through the
+ * children `enumerate` would visit, until the callback prunes, and returns
+ * Knuth's estimate of how many nodes the pruned search visits: every node on
+ * the path stands for the inverse of the odds of reaching it. The estimates
+ * average out to the true count. Children are picked in proportion to their
+ * unpruned size, which keeps the few branches holding most of the search
+ * from being missed.
+ */
+pub fn probe<'a, C, R: Rng>(
+    pool: &'a [(Ingredient, Option<usize>)],
+    count: usize,
+    prefix: &[usize],
+    space: &SearchSpace,
+    cb: &mut C,
+    rng: &mut R,
+) -> f64
+where
+    C: FnMut(&[&'a Ingredient], &RecipeState, &'a [(Ingredient, Option<usize>)]) -> bool,
+{
+    let root = Subtree {
+        start: 0,
+        prefix: prefix.to_vec(),
+    };
+    let (mut ingredients, mut state) = root.prefix_state(pool);
+    // The last ingredient added and how many of it, as `enumerate` only
+    // adds more of it or ingredients after it.
+    let mut last: Option<(usize, usize)> = None;
+    let (mut nodes, mut weight) = (0.0, 1.0);
+    while ingredients.len() < count {
+        let (from, used) = last.unwrap_or((0, 0));
+        let children: Vec<usize> = (from..pool.len())
+            .filter(|&i| {
+                let used = if i == from { used } else { 0 };
+                pool[i].1.is_none_or(|available| available > used)
+            })
+            .collect();
+        if children.is_empty() {
+            break;
+        }
+        let sizes: Vec<f64> = children
+            .iter()
+            .map(|&i| {
+                ingredients.push(&pool[i].0);
+                let size =
+                    space.descendants(pool.len() - i, &ingredients, prefix.len()) as f64 + 1.0;
+                ingredients.pop();
+                size
+            })
+            .collect();
+        let total: f64 = sizes.iter().sum();
+        let mut pick = rng.gen_range(0.0..total);
+        let mut k = 0;
+        while k + 1 < sizes.len() && pick >= sizes[k] {
+            pick -= sizes[k];
+            k += 1;
+        }
+        weight *= total / sizes[k];
+        let i = children[k];
+        last = Some((i, if i == from { used + 1 } else { 1 }));
+        ingredients.push(&pool[i].0);
+        state = &state + &pool[i].0;
+        nodes += weight;
+        if !cb(&ingredients, &state, &pool[i..]) {
+            break;
+        }
+    }
+    nodes
+}
+
+/**
+ * One deque of subtrees per worker. Workers take their newest subtree first
+ * and steal the oldest ones from each other when they run out.
+ */
+struct WorkQueue {
+    queues: Mutex<Queues>,
+}
+
+// Behind one lock, so that a snapshot of what's left to search is consistent.
+struct Queues {
+    deques: Vec<VecDeque<Subtree>>,
+    // The subtree each worker is searching.
+    running: Vec<Option<Subtree>>,
+}
+
+impl WorkQueue {
+    fn new(workers: usize, roots: Vec<Subtree>) -> WorkQueue {
+        let mut queues = Queues {
+            deques: vec![VecDeque::new(); workers],
+            running: vec![None; workers],
+        };
+        for (i, subtree) in roots.into_iter().enumerate() {
+            queues.deques[i % workers].push_back(subtree);
+        }
+        WorkQueue {
+            queues: Mutex::new(queues),
+        }
+    }
+
+    fn pop(&self, worker: usize, stop: &AtomicBool) -> Option<Subtree> {
+        loop {
+            // Leave the rest queued once the search is stopped.
+            if stop.load(Ordering::Relaxed) {
+                return None;
+            }
+            let mut queues = self.queues.lock().unwrap();
+            let mut subtree = queues.deques[worker].pop_back();
+            for i in 1..queues.deques.len() {
+                if subtree.is_some() {
+                    break;
+                }
+                let victim = (worker + i) % queues.deques.len();
+                subtree = queues.deques[victim].pop_front();
+            }
+            if subtree.is_some() {
+                queues.running[worker] = subtree.clone();
+                return subtree;
+            }
+            if queues.running.iter().all(|r| r.is_none()) {
+                return None;
+            }
+            drop(queues);
+            // Someone is still searching and may split off more work.
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /**
+     * Marks the worker's subtree as searched, queueing what it split into.
+     */
+    fn finish(&self, worker: usize, children: Vec<Subtree>) {
+        let mut queues = self.queues.lock().unwrap();
+        queues.deques[worker].extend(children);
+        queues.running[worker] = None;
+    }
+
+    /**
+     * Every subtree not searched yet, including those being searched.
+     */
+    fn frontier(&self) -> Vec<Subtree> {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .running
+            .iter()
+            .flatten()
+            .chain(queues.deques.iter().flatten())
+            .cloned()
+            .collect()
+    }
+}
+
+// Saves the subtrees left to search.
+pub type SaveFrontier<'s> = &'s (dyn Fn(&[Subtree]) + Sync);
+
+/**
+ * How to run a search over subtrees.
+ */
+pub struct Search<'s> {
+    pub threads: usize,
+    pub space: &'s SearchSpace,
+    pub progress: &'s Progress,
+    pub stop: &'s AtomicBool,
+    // Called with the subtrees left to search this often.
+    pub checkpoint: Option<(Duration, SaveFrontier<'s>)>,
+}
+
+impl Search<'_> {
+    /**
+     * Searches every subtree on `threads` worker threads, splitting big subtrees
+     * so that no worker is left alone with most of the search.
+     * `make_cb` builds each worker's callback, which gets the worker's counters
+     * to record why it prunes.
+     * Returns the subtrees left to search, if the search was stopped.
+     */
+    pub fn run<'a, F, C>(
+        &self,
+        pool: &'a [(Ingredient, Option<usize>)],
+        count: usize,
+        roots: Vec<Subtree>,
+        make_cb: F,
+    ) -> Vec<Subtree>
+    where
+        F: Fn() -> C + Sync,
+        C: FnMut(
+            &[&'a Ingredient],
+            &RecipeState,
+            &'a [(Ingredient, Option<usize>)],
+            &mut Counters,
+        ) -> bool,
+    {
+        let threads = self.threads.max(1);
+        let (space, progress, stop) = (self.space, self.progress, self.stop);
+        let split_size = space.total() / (threads as u64 * SPLIT_FACTOR) + 1;
+        progress.add_subtrees(roots.len());
+        let queue = WorkQueue::new(threads, roots);
+        let finished = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            if let Some((interval, save)) = self.checkpoint {
+                let (queue, finished) = (&queue, &finished);
+                scope.spawn(move || {
+                    let mut last = Instant::now();
+                    while !finished.load(Ordering::Relaxed) {
+                        thread::sleep(Duration::from_millis(100));
+                        if last.elapsed() >= interval {
+                            save(&queue.frontier());
+                            last = Instant::now();
+                        }
+                    }
+                });
+            }
+
+            let workers: Vec<_> = (0..threads)
+                .map(|worker| {
+                    let queue = &queue;
+                    let make_cb = &make_cb;
+                    scope.spawn(move || {
+                        let mut counters = Counters {
+                            worker,
+                            ..Default::default()
+                        };
+                        let mut worker_cb = make_cb();
+                        let mut cb = |candidate: &[&'a Ingredient],
+                                      state: &RecipeState,
+                                      pool: &'a [(Ingredient, Option<usize>)]|
+                         -> bool {
+                            if stop.load(Ordering::Relaxed) {
+                                return false;
+                            }
+                            progress.tick(&mut counters);
+                            worker_cb(candidate, state, pool, &mut counters)
+                        };
+                        while let Some(subtree) = queue.pop(worker, stop) {
+                            let children = if subtree.size(space) > split_size {
+                                subtree.split(pool, count, &mut cb)
+                            } else {
+                                subtree.search(pool, count, &mut cb);
+                                Vec::new()
+                            };
+                            // A stopped subtree may be incomplete, leave it in the frontier.
+                            if stop.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            progress.add_subtrees(children.len());
+                            progress.subtree_done(worker);
+                            queue.finish(worker, children);
+                        }
+                        progress.flush(&mut counters);
+                    })
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap();
+            }
+            finished.store(true, Ordering::Relaxed);
+        });
+
+        queue.frontier()
+    }
+}
@@ -0,0 +1,368 @@
+use crate::{utils, Ingredient};
+use ratatui::crossterm::terminal;
+use serde::{Deserialize, Serialize};
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Nodes a worker counts locally before publishing its counters.
+const FLUSH_INTERVAL: u64 = 1 << 16;
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/**
+ * How many multisets `enumerate` can visit from each suffix of the sorted pool,
+ * used to turn visited and pruned nodes into a completion estimate.
+ */
+pub struct SearchSpace {
+    caps: Vec<usize>,
+    count: usize,
+    // below[s][r] is the number of multisets of at most r ingredients
+    // from pool[s..], including the empty one.
+    below: Vec<Vec<u64>>,
+}
+
+impl SearchSpace {
+    pub fn new(pool: &[(Ingredient, Option<usize>)], count: usize) -> SearchSpace {
+        let caps: Vec<usize> = pool
+            .iter()
+            .map(|(_, available)| available.unwrap_or(count).min(count))
+            .collect();
+        let mut below = vec![vec![1u64; count + 1]; pool.len() + 1];
+        for s in (0..pool.len()).rev() {
+            for r in 0..=count {
+                below[s][r] = (0..=caps[s].min(r))
+                    .fold(0u64, |sum, k| sum.saturating_add(below[s + 1][r - k]));
+            }
+        }
+        SearchSpace { caps, count, below }
+    }
+
+    /**
+     * Every non-empty recipe, i.e. the nodes an unpruned search visits.
+     */
+    pub fn total(&self) -> u64 {
+        self.below[0][self.count] - 1
+    }
+
+    /**
+     * The nodes below a candidate the search won't visit if it prunes there.
+     * `pool_len` is the length of the pool the candidate's last ingredient heads,
+     * and the candidate starts with the `seeded` ingredients of --start-with,
+     * which the pool's caps already leave out.
+     */
+    pub fn descendants(&self, pool_len: usize, candidate: &[&Ingredient], seeded: usize) -> u64 {
+        let start = self.caps.len() - pool_len;
+        let last = candidate.last().unwrap();
+        let used = candidate[seeded..]
+            .iter()
+            .rev()
+            .take_while(|i| std::ptr::eq(**i, *last))
+            .count();
+        let slots = self.count - candidate.len();
+        let more = self.caps[start].saturating_sub(used).min(slots);
+        (0..=more).fold(0u64, |sum, k| {
+            sum.saturating_add(self.below[start + 1][slots - k])
+        }) - 1
+    }
+
+    /**
+     * The nodes under pool[start] once `used` ingredients are already in,
+     * counting every recipe that adds at least one of it.
+     */
+    pub fn subtree(&self, start: usize, used: usize) -> u64 {
+        let slots = self.count - used;
+        (1..=self.caps[start].min(slots)).fold(0u64, |sum, k| {
+            sum.saturating_add(self.below[start + 1][slots - k])
+        })
+    }
+}
+
+/**
+ * A worker's own counters, published to the shared `Progress` now and then.
+ */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Counters {
+    pub nodes: u64,
+    pub over_max: u64,
+    pub below_min: u64,
+    pub over_budget: u64,
+    pub ratio_mismatch: u64,
+    // A magimin can't reach its share of the ratio any more.
+    pub unreachable: u64,
+    // Nodes skipped by pruning, and those skipped by the reachability bounds alone.
+    pub skipped: u64,
+    pub unreachable_skipped: u64,
+    // Which worker counted these, a thread or a process of a distributed search.
+    #[serde(skip)]
+    pub worker: usize,
+}
+
+/**
+ * What one worker has searched so far.
+ */
+#[derive(Clone, Copy, Debug, Default)]
+struct WorkerProgress {
+    nodes: u64,
+    subtrees_done: usize,
+}
+
+#[derive(Default)]
+pub struct Progress {
+    nodes: AtomicU64,
+    over_max: AtomicU64,
+    below_min: AtomicU64,
+    over_budget: AtomicU64,
+    ratio_mismatch: AtomicU64,
+    unreachable: AtomicU64,
+    skipped: AtomicU64,
+    unreachable_skipped: AtomicU64,
+    subtrees: AtomicUsize,
+    subtrees_done: AtomicUsize,
+    // Indexed by Counters::worker, grown as workers show up.
+    workers: Mutex<Vec<WorkerProgress>>,
+}
+
+impl Progress {
+    /**
+     * Counts a node, publishing the worker's counters every so often.
+     */
+    pub fn tick(&self, counters: &mut Counters) {
+        counters.nodes += 1;
+        if counters.nodes >= FLUSH_INTERVAL {
+            self.flush(counters);
+        }
+    }
+
+    pub fn flush(&self, counters: &mut Counters) {
+        self.nodes.fetch_add(counters.nodes, Ordering::Relaxed);
+        self.over_max
+            .fetch_add(counters.over_max, Ordering::Relaxed);
+        self.below_min
+            .fetch_add(counters.below_min, Ordering::Relaxed);
+        self.over_budget
+            .fetch_add(counters.over_budget, Ordering::Relaxed);
+        self.ratio_mismatch
+            .fetch_add(counters.ratio_mismatch, Ordering::Relaxed);
+        self.unreachable
+            .fetch_add(counters.unreachable, Ordering::Relaxed);
+        self.skipped.fetch_add(counters.skipped, Ordering::Relaxed);
+        self.unreachable_skipped
+            .fetch_add(counters.unreachable_skipped, Ordering::Relaxed);
+        if counters.nodes > 0 {
+            self.worker(counters.worker, |worker| worker.nodes += counters.nodes);
+        }
+        *counters = Counters {
+            worker: counters.worker,
+            ..Default::default()
+        };
+    }
+
+    pub fn add_subtrees(&self, n: usize) {
+        self.subtrees.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn subtree_done(&self, worker: usize) {
+        self.subtrees_done.fetch_add(1, Ordering::Relaxed);
+        self.worker(worker, |worker| worker.subtrees_done += 1);
+    }
+
+    fn worker(&self, worker: usize, update: impl FnOnce(&mut WorkerProgress)) {
+        let mut workers = self.workers.lock().unwrap();
+        if workers.len() <= worker {
+            workers.resize(worker + 1, WorkerProgress::default());
+        }
+        update(&mut workers[worker]);
+    }
+
+    /**
+     * The subtrees each worker has searched, e.g. "12 9 11".
+     */
+    fn worker_subtrees(&self) -> String {
+        let workers = self.workers.lock().unwrap();
+        let done: Vec<String> = workers
+            .iter()
+            .map(|worker| worker.subtrees_done.to_string())
+            .collect();
+        done.join(" ")
+    }
+
+    /**
+     * Each worker's share of the nodes visited and the subtrees it searched.
+     */
+    fn worker_status(&self) -> String {
+        let workers = self.workers.lock().unwrap();
+        let nodes = workers
+            .iter()
+            .map(|worker| worker.nodes)
+            .sum::<u64>()
+            .max(1);
+        let status: Vec<String> = workers
+            .iter()
+            .enumerate()
+            .map(|(i, worker)| {
+                format!(
+                    "worker {}: {} nodes ({:.1}%), {} subtrees",
+                    i,
+                    worker.nodes,
+                    100.0 * worker.nodes as f64 / nodes as f64,
+                    worker.subtrees_done
+                )
+            })
+            .collect();
+        status.join("; ")
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    /**
+     * How much of a search of `total` nodes has been visited or pruned.
+     */
+    pub fn fraction(&self, total: u64) -> f64 {
+        (self.covered() as f64 / total.max(1) as f64).min(1.0)
+    }
+
+    /**
+     * The nodes visited or pruned so far.
+     */
+    pub fn covered(&self) -> u64 {
+        let nodes = self.nodes.load(Ordering::Relaxed);
+        nodes.saturating_add(self.skipped.load(Ordering::Relaxed))
+    }
+
+    fn eta(&self, total: u64, elapsed: Duration) -> String {
+        let fraction = self.fraction(total);
+        if fraction > 0.0 {
+            utils::format_duration(elapsed.as_secs_f64() * (1.0 - fraction) / fraction)
+        } else {
+            "?".to_owned()
+        }
+    }
+
+    /**
+     * The short line kept up to date while searching, without the prune counts.
+     * Subtrees done per worker come last, where a narrow terminal cuts them off.
+     */
+    fn live_status(&self, total: u64, elapsed: Duration) -> String {
+        let nodes = self.nodes.load(Ordering::Relaxed);
+        format!(
+            "{:.1}%, eta {}, {} nodes ({:.0}/s), {}/{} subtrees done, per worker {}",
+            100.0 * self.fraction(total),
+            self.eta(total, elapsed),
+            nodes,
+            nodes as f64 / elapsed.as_secs_f64().max(1e-3),
+            self.subtrees_done.load(Ordering::Relaxed),
+            self.subtrees.load(Ordering::Relaxed),
+            self.worker_subtrees(),
+        )
+    }
+
+    fn status(&self, total: u64, elapsed: Duration) -> String {
+        let nodes = self.nodes.load(Ordering::Relaxed);
+        format!(
+            "{} nodes ({:.0}/s), pruned {} over max, {} below min, {} over budget, {} ratio mismatch, \
+             {} unreachable (up to {:.1}x fewer nodes); {}/{} subtrees done, {:.1}%, eta {}",
+            nodes,
+            nodes as f64 / elapsed.as_secs_f64().max(1e-3),
+            self.over_max.load(Ordering::Relaxed),
+            self.below_min.load(Ordering::Relaxed),
+            self.over_budget.load(Ordering::Relaxed),
+            self.ratio_mismatch.load(Ordering::Relaxed),
+            self.unreachable.load(Ordering::Relaxed),
+            // Without the reachability bounds the search would also visit the
+            // nodes they skipped, unless another check pruned those first.
+            (nodes as f64 + self.unreachable_skipped.load(Ordering::Relaxed) as f64)
+                / nodes.max(1) as f64,
+            self.subtrees_done.load(Ordering::Relaxed),
+            self.subtrees.load(Ordering::Relaxed),
+            100.0 * self.fraction(total),
+            self.eta(total, elapsed),
+        )
+    }
+}
+
+/**
+ * Keeps a status line on stderr up to date until `finished` is set,
+ * then prints the final counts.
+ */
+pub fn spawn_reporter(
+    progress: Arc<Progress>,
+    total: u64,
+    finished: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    let start = Instant::now();
+    thread::spawn(move || {
+        let live = std::io::stderr().is_terminal() && log::log_enabled!(log::Level::Info);
+        while !finished.load(Ordering::Relaxed) {
+            thread::sleep(REFRESH_INTERVAL);
+            if live {
+                // A line that wraps can't be cleared with \r, so it's cut to fit.
+                let width = terminal::size().map_or(80, |(columns, _)| columns as usize);
+                let status = progress.live_status(total, start.elapsed());
+                let status: String = status.chars().take(width.saturating_sub(1)).collect();
+                eprint!("\r\x1b[K{}", status);
+                let _ = std::io::stderr().flush();
+            }
+        }
+        if live {
+            eprint!("\r\x1b[K");
+        }
+        log::info!("{}", progress.status(total, start.elapsed()));
+        log::info!("{}", progress.worker_status());
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchSpace;
+    use crate::parallel::Subtree;
+    use crate::test_support::pool;
+    use crate::{seed, Recipe};
+
+    #[test]
+    fn descendants_of_a_seeded_prefix_match_the_search() {
+        let mut pool = pool();
+        let mut target = Recipe::HEALTH.target();
+        target.count = 6;
+        // With four Dewdrop the cap, not the cauldron, bounds how many more the
+        // search adds after the one the prefix ends with.
+        let dewdrop = pool
+            .iter()
+            .position(|(ingredient, _)| ingredient.name == "Dewdrop")
+            .unwrap();
+        pool[dewdrop].1 = Some(4);
+        let start_with = ["Dewdrop".to_owned(), "Marshmoss".to_owned()];
+        let prefix = seed(&mut pool, &[], &target, &start_with).unwrap();
+        let space = SearchSpace::new(&pool, target.count);
+
+        for root in Subtree::roots(pool.len(), &prefix) {
+            // Every node in visiting order, with the nodes the space says are below it.
+            let mut nodes: Vec<(usize, u64)> = Vec::new();
+            root.search(&pool, target.count, &mut |ingredients, _, pool_from| {
+                let below = space.descendants(pool_from.len(), ingredients, prefix.len());
+                nodes.push((ingredients.len(), below));
+                true
+            });
+            assert_eq!(
+                nodes.len() as u64,
+                root.size(&space),
+                "subtree {}",
+                root.start
+            );
+            for (i, &(len, below)) in nodes.iter().enumerate() {
+                let visited = nodes[i + 1..]
+                    .iter()
+                    .take_while(|&&(deeper, _)| deeper > len)
+                    .count();
+                assert_eq!(
+                    below, visited as u64,
+                    "node {} of subtree {}",
+                    i, root.start
+                );
+            }
+        }
+    }
+}
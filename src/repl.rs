@@ -0,0 +1,369 @@
+use crate::output::{Format, Writer};
+use crate::parallel::Subtree;
+use crate::progress::Progress;
+use crate::{
+    dominance, eval, print, solve, utils, Args, Cauldron, Ingredient, IngredientRatio, Pool,
+    PotionRecipe, Recipe, SearchOptions, SharedState, SolveAlgorithm, Stability,
+};
+use clap::{ArgAction, Parser, Subcommand};
+use std::collections::HashSet;
+use std::io::{BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// How many recipes a search lists unless asked for more.
+const LISTED: usize = 10;
+
+#[derive(Debug, Parser)]
+#[command(multicall = true)]
+struct Line {
+    #[command(subcommand)]
+    command: Action,
+}
+
+#[derive(Debug, Subcommand)]
+enum Action {
+    /// Search with the current settings.
+    Search,
+    /// List the recipes the last search found, best first.
+    List {
+        #[arg(default_value_t = LISTED)]
+        count: usize,
+    },
+    /// Break a recipe from the last search down by ingredient and magimin.
+    Show {
+        /// Its number in the list.
+        number: usize,
+    },
+    /// Work out the potion a recipe such as "3x Feyberry 2x MandrakeRoot 1x BogBeet" makes.
+    Eval {
+        #[arg(required = true)]
+        ingredients: Vec<String>,
+    },
+    /// Change the recipe to search for.
+    Recipe {
+        #[arg(value_enum)]
+        recipe: Recipe,
+    },
+    /// Change how to search.
+    Mode {
+        #[arg(value_enum)]
+        mode: SolveAlgorithm,
+    },
+    /// Change how many ingredients and magimins the cauldron holds.
+    Cauldron { ingredients: usize, magimins: usize },
+    /// Only report potions with at least this many magimins.
+    MinMagimins { magimins: usize },
+    /// Largest per-magimin deviation from the target ratio accepted in approximate mode.
+    MaxDeviation {
+        #[arg(value_parser = utils::parse_percent)]
+        percent: f64,
+    },
+    /// Only report potions at least this stable.
+    MinStability {
+        #[arg(value_enum)]
+        stability: Stability,
+    },
+    /// Only report recipes costing at most this much, or any amount if left out.
+    MaxCost { price: Option<usize> },
+    /// Only search recipes that start with these ingredients, or any if left out.
+    StartWith { ingredients: Vec<String> },
+    /// Search every ingredient, even those another one is at least as good as.
+    KeepDominated {
+        #[arg(action = ArgAction::Set)]
+        keep: bool,
+    },
+    /// Change what an ingredient costs.
+    Price { ingredient: String, price: usize },
+    /// Change how many of an ingredient there are, or any number if left out.
+    Available {
+        ingredient: String,
+        count: Option<usize>,
+    },
+    /// Print the current settings.
+    Settings,
+    /// Leave.
+    #[command(alias = "exit")]
+    Quit,
+}
+
+/**
+ * What the last search found, kept to list and show.
+ */
+struct Found {
+    // The pool the recipes are made of, as it was for that search.
+    pool: Vec<(Ingredient, Option<usize>)>,
+    target: IngredientRatio,
+    // Best first, with the groups of interchangeable ingredients each uses.
+    recipes: Vec<(Vec<String>, Vec<Vec<String>>)>,
+}
+
+impl Found {
+    fn recipe(&self, i: usize) -> PotionRecipe<'_> {
+        // Made of this pool's ingredients, so every name is in it.
+        PotionRecipe::from_names(&self.pool, std::slice::from_ref(&self.recipes[i].0))
+            .unwrap()
+            .remove(0)
+    }
+}
+
+struct Session {
+    // The settings, changed in place as the session goes on.
+    args: Args,
+    catalog: Vec<(Ingredient, Option<usize>)>,
+    cauldron: Cauldron,
+    stop: Arc<AtomicBool>,
+    found: Option<Found>,
+}
+
+impl Session {
+    fn target(&self) -> IngredientRatio {
+        self.cauldron.target(self.args.recipe)
+    }
+
+    /**
+     * Changes the cauldron, if a search can fill it.
+     */
+    fn set_cauldron(&mut self, cauldron: Cauldron) -> Result<(), String> {
+        cauldron.check()?;
+        self.cauldron = cauldron;
+        Ok(())
+    }
+
+    fn ingredient(&mut self, name: &str) -> Result<&mut (Ingredient, Option<usize>), String> {
+        self.catalog
+            .iter_mut()
+            .find(|(ingredient, _)| ingredient.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("there's no ingredient called {}", name))
+    }
+
+    fn search(&mut self) -> Result<(), String> {
+        let target = self.target();
+        let pool = Pool::new(self.catalog.clone(), &target, &self.args)?;
+        let substitutions = dominance::Substitutions::new(&pool.ingredients, &pool.equivalences);
+        self.stop.store(false, Ordering::Relaxed);
+        let shared_state = SharedState {
+            ingredients: &pool.ingredients,
+            target: target.clone(),
+            acc: Mutex::new(Vec::new()),
+            max_cost: self.args.max_cost,
+            start_with: pool.prefix.clone(),
+            stop: self.stop.clone(),
+            progress: Arc::new(Progress::default()),
+        };
+        let timer = utils::Timer::start(self.args.timeout, &self.stop);
+        let complete = solve(
+            &self.args,
+            &shared_state,
+            SearchOptions {
+                threads: self.args.threads,
+                roots: Subtree::roots(pool.ingredients.len(), &pool.prefix),
+                checkpoint: None,
+            },
+        );
+        drop(timer);
+        // Back at the prompt, a Ctrl-C stops nothing yet, and only a second one exits.
+        self.stop.store(false, Ordering::Relaxed);
+
+        let mut recipes = shared_state.acc.lock().unwrap();
+        self.args.objective().rank(&target, &mut recipes);
+        let mut seen = HashSet::new();
+        let distinct: Vec<(Vec<String>, Vec<Vec<String>>)> = recipes
+            .iter()
+            .filter(|recipe| seen.insert(substitutions.key(&recipe.ingredients)))
+            .map(|recipe| {
+                let classes = substitutions.used(&recipe.ingredients);
+                (
+                    recipe.names(),
+                    classes.iter().map(|class| class.to_vec()).collect(),
+                )
+            })
+            .collect();
+        if !complete {
+            println!("Search stopped early: found {} so far", distinct.len());
+        } else {
+            println!("Found {} recipes", distinct.len());
+        }
+        drop(recipes);
+        self.found = Some(Found {
+            pool: pool.ingredients.clone(),
+            target,
+            recipes: distinct,
+        });
+        self.list(LISTED)
+    }
+
+    fn found(&self) -> Result<&Found, String> {
+        self.found
+            .as_ref()
+            .ok_or_else(|| "nothing searched yet".to_owned())
+    }
+
+    fn list(&self, count: usize) -> Result<(), String> {
+        let found = self.found()?;
+        for i in 0..count.min(found.recipes.len()) {
+            let recipe = found.recipe(i);
+            let attributes = &recipe.attributes;
+            print(
+                &format!("#{} ", i + 1),
+                recipe.ingredients.len(),
+                attributes.magimins.total(),
+                attributes.sense_score(),
+                attributes.stability(&found.target),
+                recipe.cost,
+                &recipe.ingredients,
+            );
+            for class in found.recipes[i].1.iter() {
+                println!("\tinterchangeable: {}", class.join(", "));
+            }
+        }
+        if found.recipes.len() > count {
+            println!("... and {} more", found.recipes.len() - count);
+        }
+        Ok(())
+    }
+
+    fn show(&self, number: usize) -> Result<(), String> {
+        let found = self.found()?;
+        let i = number
+            .checked_sub(1)
+            .filter(|&i| i < found.recipes.len())
+            .ok_or_else(|| format!("there's no recipe #{}", number))?;
+        Writer::new(Format::Table, Vec::new()).recipe(
+            &found.target,
+            &found.recipe(i),
+            found.recipes[i]
+                .1
+                .iter()
+                .map(|class| class.as_slice())
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn settings(&self) {
+        let args = &self.args;
+        println!("recipe {:?}, {:?} search", args.recipe, args.mode);
+        println!(
+            "cauldron of {} ingredients and {} magimins, at least {} magimins",
+            self.cauldron.count, self.cauldron.max, self.cauldron.min
+        );
+        println!(
+            "max deviation {}%, min stability {}, max cost {}",
+            args.max_deviation,
+            args.min_stability,
+            args.max_cost
+                .map_or("any".to_owned(), |price| format!("${}", price))
+        );
+        println!(
+            "starting with {}, {} dominated ingredients",
+            args.start_with.as_deref().unwrap_or("anything"),
+            if args.keep_dominated {
+                "keeping"
+            } else {
+                "dropping"
+            }
+        );
+    }
+
+    /**
+     * Does what the line asks, returning false to leave.
+     */
+    fn run(&mut self, action: Action) -> Result<bool, String> {
+        match action {
+            Action::Search => self.search()?,
+            Action::List { count } => self.list(count)?,
+            Action::Show { number } => self.show(number)?,
+            Action::Eval { ingredients } => eval(
+                &self.catalog,
+                &self.target(),
+                self.args.recipe,
+                &ingredients.join(" "),
+            )?,
+            Action::Recipe { recipe } => self.args.recipe = recipe,
+            Action::Mode { mode } => self.args.mode = mode,
+            Action::Cauldron {
+                ingredients,
+                magimins,
+            } => self.set_cauldron(Cauldron {
+                count: ingredients,
+                max: magimins,
+                ..self.cauldron
+            })?,
+            Action::MinMagimins { magimins } => self.set_cauldron(Cauldron {
+                min: magimins,
+                ..self.cauldron
+            })?,
+            Action::MaxDeviation { percent } => self.args.max_deviation = percent,
+            Action::MinStability { stability } => self.args.min_stability = stability,
+            Action::MaxCost { price } => self.args.max_cost = price,
+            Action::StartWith { ingredients } => {
+                self.args.start_with = (!ingredients.is_empty()).then(|| ingredients.join(" "))
+            }
+            Action::KeepDominated { keep } => self.args.keep_dominated = keep,
+            Action::Price { ingredient, price } => self.ingredient(&ingredient)?.0.price = price,
+            Action::Available { ingredient, count } => self.ingredient(&ingredient)?.1 = count,
+            Action::Settings => self.settings(),
+            Action::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+/**
+ * Reads commands from stdin until quit, searching the catalog as often as
+ * asked without loading it again.
+ */
+pub fn run(args: Args, catalog: Vec<(Ingredient, Option<usize>)>) {
+    let mut session = Session {
+        cauldron: Cauldron::new(args.recipe),
+        args,
+        catalog,
+        stop: Arc::new(AtomicBool::new(false)),
+        found: None,
+    };
+    // Ctrl-C stops a search, a second one exits.
+    signal_hook::flag::register_conditional_shutdown(
+        signal_hook::consts::SIGINT,
+        1,
+        session.stop.clone(),
+    )
+    .unwrap();
+    signal_hook::flag::register(signal_hook::consts::SIGINT, session.stop.clone()).unwrap();
+
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        println!("Type help for the commands");
+    }
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        if interactive {
+            print!("> ");
+            std::io::stdout().flush().unwrap();
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                log::error!("Can't read the command: {}", e);
+                break;
+            }
+            None => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let action = match Line::try_parse_from(words) {
+            Ok(line) => line.command,
+            Err(e) => {
+                e.print().unwrap();
+                continue;
+            }
+        };
+        match session.run(action) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => log::error!("{}", e),
+        }
+    }
+}
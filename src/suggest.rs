@@ -0,0 +1,94 @@
+use crate::{output, rms, IngredientRatio, RecipeState, SharedState};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/**
+ * What makes one ingredient a better next pick than another.
+ */
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Rank {
+    // Leaves the most recipes within reach.
+    Completions,
+    // Leads to the cheapest recipe.
+    Cost,
+    // Keeps the potion closest to the ratio right away.
+    Error,
+}
+
+/**
+ * An ingredient that could go in next, and where it leads.
+ */
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    pub ingredient: String,
+    // Recipes found that add it to the ones the recipe starts with.
+    pub completions: usize,
+    pub cheapest: usize,
+    // How far the potion is off the ratio once it's in, in magimins (rms).
+    pub error: f64,
+}
+
+/**
+ * How far a partial recipe is off the ratio, scaled to its own total.
+ */
+fn error(target: &IngredientRatio, state: &RecipeState) -> f64 {
+    let total = state.attributes.magimins.total();
+    rms(
+        (total / &target.magimins).as_array(),
+        total,
+        state.attributes.magimins.as_array(),
+    )
+}
+
+/**
+ * Ranks every ingredient that some recipe found adds to those it starts with.
+ */
+pub fn suggest(shared_state: &SharedState, rank: Rank) -> Vec<Suggestion> {
+    let (pool, target) = (shared_state.ingredients, &shared_state.target);
+    let index: HashMap<&str, usize> = pool
+        .iter()
+        .enumerate()
+        .map(|(i, (ingredient, _))| (ingredient.name.as_str(), i))
+        .collect();
+    let mut started = vec![0; pool.len()];
+    let mut state = RecipeState::default();
+    for &i in shared_state.start_with.iter() {
+        started[i] += 1;
+        state = &state + &pool[i].0;
+    }
+
+    let mut completions = vec![0; pool.len()];
+    let mut cheapest = vec![usize::MAX; pool.len()];
+    for recipe in shared_state.acc.lock().unwrap().iter() {
+        for (ingredient, count) in output::counts(&recipe.ingredients) {
+            let i = index[ingredient.name.as_str()];
+            if count > started[i] {
+                completions[i] += 1;
+                cheapest[i] = cheapest[i].min(recipe.cost);
+            }
+        }
+    }
+
+    let mut suggestions: Vec<Suggestion> = (0..pool.len())
+        .filter(|&i| completions[i] > 0)
+        .map(|i| Suggestion {
+            ingredient: pool[i].0.name.clone(),
+            completions: completions[i],
+            cheapest: cheapest[i],
+            error: error(target, &(&state + &pool[i].0)),
+        })
+        .collect();
+    suggestions.sort_by(|a, b| {
+        let by_completions = b.completions.cmp(&a.completions);
+        let by_cost = a.cheapest.cmp(&b.cheapest);
+        let by_error = a.error.total_cmp(&b.error);
+        match rank {
+            Rank::Completions => by_completions.then(by_cost).then(by_error),
+            Rank::Cost => by_cost.then(by_completions).then(by_error),
+            Rank::Error => by_error.then(by_completions).then(by_cost),
+        }
+        .then_with(|| a.ingredient.cmp(&b.ingredient))
+    });
+    suggestions
+}
@@ -0,0 +1,45 @@
+use crate::{Ingredient, PotionRecipe};
+
+/**
+ * A small catalog, in the input file format, that still has dominated,
+ * interchangeable and limited ingredients.
+ */
+pub const CATALOG: &str = "
+    Trollstool a20 b20 $30
+    Trollcap a20 b20 $30
+    Slimeberry a40 +taste $20
+    Slimepod a40 $22
+    Slimerind a40 -taste $26
+    Boneroot b40 -feel $25
+    Marshmoss a30 b10 $15
+    Wispcap a10 b30 +sight $18
+    Emberleaf a20 c10 $12
+    Giantseed a60 b60 $90
+    Dewdrop b10 x2 $5
+    Dewbead b10 x3 $5
+";
+
+/**
+ * CATALOG's lines, trimmed.
+ */
+pub fn catalog_lines() -> impl Iterator<Item = String> {
+    CATALOG.lines().map(|line| line.trim().to_owned())
+}
+
+/**
+ * CATALOG's ingredients, in pool order.
+ */
+pub fn pool() -> Vec<(Ingredient, Option<usize>)> {
+    let mut pool = Ingredient::parse(catalog_lines());
+    pool.sort();
+    pool
+}
+
+/**
+ * The recipes' ingredient names, sorted so that two searches can be compared.
+ */
+pub fn names(recipes: &[PotionRecipe]) -> Vec<Vec<String>> {
+    let mut names: Vec<Vec<String>> = recipes.iter().map(|recipe| recipe.names()).collect();
+    names.sort();
+    names
+}
@@ -0,0 +1,685 @@
+use crate::dominance::Substitutions;
+use crate::objective::Objective;
+use crate::output;
+use crate::parallel::Subtree;
+use crate::progress::{Progress, SearchSpace};
+use crate::{
+    solve, utils, Args, Cauldron, Ingredient, IngredientRatio, Pool, PotionRecipe, Recipe,
+    SearchOptions, SharedState, SolveAlgorithm, Stability,
+};
+use clap::ValueEnum;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+// How often the screen catches up with the search.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+// Recipes kept in the ranked list, best first.
+const RANKED: usize = 1000;
+// Rows PageUp and PageDown move by.
+const PAGE: usize = 10;
+// The most ingredients and magimins the target pane goes up to.
+const MOST_INGREDIENTS: usize = 99;
+const MOST_MAGIMINS: usize = 9999;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Pane {
+    Ingredients,
+    Target,
+    Results,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Setting {
+    Recipe,
+    Mode,
+    Ingredients,
+    MaxMagimins,
+    MinMagimins,
+    MaxDeviation,
+    MinStability,
+    MaxCost,
+    KeepDominated,
+}
+
+const SETTINGS: [Setting; 9] = [
+    Setting::Recipe,
+    Setting::Mode,
+    Setting::Ingredients,
+    Setting::MaxMagimins,
+    Setting::MinMagimins,
+    Setting::MaxDeviation,
+    Setting::MinStability,
+    Setting::MaxCost,
+    Setting::KeepDominated,
+];
+
+/**
+ * A change asked of the selected ingredient or setting.
+ */
+#[derive(Copy, Clone)]
+enum Edit {
+    Less,
+    More,
+    Digit(usize),
+    Erase,
+    Toggle,
+}
+
+enum Next {
+    Search,
+    Quit,
+}
+
+/**
+ * The variant after (or before) this one, wrapping around.
+ */
+fn cycle<T: ValueEnum>(value: &T, forward: bool) -> T {
+    let variants = T::value_variants();
+    let i = variants
+        .iter()
+        .position(|variant| name(variant) == name(value))
+        .unwrap();
+    let step = if forward { 1 } else { variants.len() - 1 };
+    variants[(i + step) % variants.len()].clone()
+}
+
+fn name<T: ValueEnum>(value: &T) -> String {
+    value.to_possible_value().unwrap().get_name().to_owned()
+}
+
+/**
+ * Types into a number: digits are appended, erasing drops the last one.
+ */
+fn type_number(value: usize, edit: Edit, most: usize) -> usize {
+    match edit {
+        Edit::Digit(digit) => (value * 10 + digit).min(most),
+        Edit::Erase => value / 10,
+        _ => value,
+    }
+}
+
+/**
+ * A count that can also be left unlimited, which erasing the last digit does.
+ */
+fn type_limit(value: Option<usize>, edit: Edit, most: usize) -> Option<usize> {
+    match (value, edit) {
+        (None, Edit::Digit(digit)) => Some(digit),
+        (Some(n), Edit::Erase) if n < 10 => None,
+        (Some(n), _) => Some(type_number(n, edit, most)),
+        (None, _) => None,
+    }
+}
+
+/**
+ * One search and the recipes it found so far.
+ */
+struct Run<'a, 's> {
+    shared_state: &'s SharedState<'a>,
+    substitutions: &'s Substitutions,
+    objective: Objective,
+    // Nodes an exact or approximate search visits, to show how far along it is.
+    total: Option<u64>,
+    // Set once the search returns, to whether it ran to the end.
+    finished: &'s OnceLock<bool>,
+    // Best first, one per group of recipes that only differ by interchangeable ingredients.
+    ranked: Vec<PotionRecipe<'a>>,
+    seen: HashSet<Vec<&'s str>>,
+    // How many of the recipes found have been ranked.
+    taken: usize,
+}
+
+impl<'a, 's> Run<'a, 's> {
+    /**
+     * Ranks the recipes found since the last look.
+     */
+    fn catch_up(&mut self) {
+        let acc = self.shared_state.acc.lock().unwrap();
+        if acc.len() == self.taken {
+            return;
+        }
+        for recipe in acc[self.taken..].iter() {
+            if self
+                .seen
+                .insert(self.substitutions.key(&recipe.ingredients))
+            {
+                self.ranked
+                    .push(PotionRecipe::new(recipe.ingredients.clone()));
+            }
+        }
+        self.taken = acc.len();
+        drop(acc);
+        self.objective
+            .rank(&self.shared_state.target, &mut self.ranked);
+        self.ranked.truncate(RANKED);
+    }
+
+    fn status(&self) -> String {
+        let state = match (self.finished.get(), self.total) {
+            (None, Some(total)) => format!(
+                "searching, {:.1}%",
+                100.0 * self.shared_state.progress.fraction(total)
+            ),
+            (None, None) => "searching".to_owned(),
+            (Some(false), _) => "stopped".to_owned(),
+            (Some(true), _) => "done".to_owned(),
+        };
+        format!("{} found, {}", self.seen.len(), state)
+    }
+}
+
+/**
+ * What the screen shows and lets the user change, kept from one search to the next.
+ */
+struct App {
+    // The settings, changed in place as the user goes.
+    args: Args,
+    catalog: Vec<(Ingredient, Option<usize>)>,
+    // Whether each catalog ingredient goes into the search.
+    included: Vec<bool>,
+    cauldron: Cauldron,
+    pane: Pane,
+    ingredient: ListState,
+    setting: ListState,
+    result: ListState,
+    // Showing the breakdown of the selected result instead of the list.
+    detail: bool,
+    // The settings changed since the search started.
+    changed: bool,
+    // Why the last search couldn't start.
+    error: Option<String>,
+}
+
+impl App {
+    fn target(&self) -> IngredientRatio {
+        self.cauldron.target(self.args.recipe)
+    }
+
+    fn value(&self, setting: Setting) -> String {
+        let args = &self.args;
+        match setting {
+            Setting::Recipe => name(&args.recipe),
+            Setting::Mode => name(&args.mode),
+            Setting::Ingredients => self.cauldron.count.to_string(),
+            Setting::MaxMagimins => self.cauldron.max.to_string(),
+            Setting::MinMagimins => self.cauldron.min.to_string(),
+            Setting::MaxDeviation => format!("{}%", args.max_deviation),
+            Setting::MinStability => args.min_stability.to_string(),
+            Setting::MaxCost => args
+                .max_cost
+                .map_or("any".to_owned(), |price| format!("${}", price)),
+            Setting::KeepDominated => args.keep_dominated.to_string(),
+        }
+    }
+
+    fn label(setting: Setting) -> &'static str {
+        match setting {
+            Setting::Recipe => "Recipe",
+            Setting::Mode => "Mode",
+            Setting::Ingredients => "Ingredients",
+            Setting::MaxMagimins => "Max magimins",
+            Setting::MinMagimins => "Min magimins",
+            Setting::MaxDeviation => "Max deviation",
+            Setting::MinStability => "Min stability",
+            Setting::MaxCost => "Max cost",
+            Setting::KeepDominated => "Keep dominated",
+        }
+    }
+
+    fn edit_setting(&mut self, setting: Setting, edit: Edit) {
+        let (args, cauldron) = (&mut self.args, &mut self.cauldron);
+        let forward = matches!(edit, Edit::More | Edit::Toggle);
+        match (setting, edit) {
+            (Setting::Recipe, Edit::Less | Edit::More) => {
+                args.recipe = cycle::<Recipe>(&args.recipe, forward);
+                // Each recipe comes with its own cauldron.
+                *cauldron = Cauldron::new(args.recipe);
+            }
+            (Setting::Mode, Edit::Less | Edit::More) => {
+                args.mode = cycle::<SolveAlgorithm>(&args.mode, forward)
+            }
+            (Setting::MinStability, Edit::Less | Edit::More) => {
+                args.min_stability = cycle::<Stability>(&args.min_stability, forward)
+            }
+            (Setting::KeepDominated, Edit::Less | Edit::More | Edit::Toggle) => {
+                args.keep_dominated = !args.keep_dominated
+            }
+            (Setting::MaxDeviation, Edit::Less) => {
+                args.max_deviation = (args.max_deviation - 0.5).max(0.0)
+            }
+            (Setting::MaxDeviation, Edit::More) => {
+                args.max_deviation = (args.max_deviation + 0.5).min(100.0)
+            }
+            (Setting::Ingredients, Edit::Less) => cauldron.count = cauldron.count.saturating_sub(1),
+            (Setting::Ingredients, Edit::More) => {
+                cauldron.count = (cauldron.count + 1).min(MOST_INGREDIENTS)
+            }
+            (Setting::Ingredients, _) => {
+                cauldron.count = type_number(cauldron.count, edit, MOST_INGREDIENTS)
+            }
+            (Setting::MaxMagimins, Edit::Less) => cauldron.max = cauldron.max.saturating_sub(5),
+            (Setting::MaxMagimins, Edit::More) => {
+                cauldron.max = (cauldron.max + 5).min(MOST_MAGIMINS)
+            }
+            (Setting::MaxMagimins, _) => {
+                cauldron.max = type_number(cauldron.max, edit, MOST_MAGIMINS)
+            }
+            (Setting::MinMagimins, Edit::Less) => cauldron.min = cauldron.min.saturating_sub(5),
+            (Setting::MinMagimins, Edit::More) => {
+                cauldron.min = (cauldron.min + 5).min(MOST_MAGIMINS)
+            }
+            (Setting::MinMagimins, _) => {
+                cauldron.min = type_number(cauldron.min, edit, MOST_MAGIMINS)
+            }
+            (Setting::MaxCost, Edit::Less) => {
+                args.max_cost = args.max_cost.map(|price| price.saturating_sub(10))
+            }
+            (Setting::MaxCost, Edit::More) => args.max_cost = args.max_cost.map(|price| price + 10),
+            (Setting::MaxCost, _) => args.max_cost = type_limit(args.max_cost, edit, 99999),
+            _ => return,
+        }
+        self.changed = true;
+    }
+
+    fn edit_ingredient(&mut self, i: usize, edit: Edit) {
+        let available = &mut self.catalog[i].1;
+        match edit {
+            Edit::Toggle => self.included[i] = !self.included[i],
+            // Counting down from any number there are starts just below the highest count worth having.
+            Edit::Less => {
+                *available = match *available {
+                    None => Some(self.cauldron.count.saturating_sub(1)),
+                    Some(n) => Some(n.saturating_sub(1)),
+                }
+            }
+            Edit::More => {
+                *available = match *available {
+                    Some(n) if n + 1 >= self.cauldron.count => None,
+                    Some(n) => Some(n + 1),
+                    None => None,
+                }
+            }
+            _ => *available = type_limit(*available, edit, 999),
+        }
+        self.changed = true;
+    }
+
+    fn edit(&mut self, edit: Edit) {
+        match self.pane {
+            Pane::Ingredients => {
+                if let Some(i) = self.ingredient.selected() {
+                    self.edit_ingredient(i, edit);
+                }
+            }
+            Pane::Target => {
+                if let Some(i) = self.setting.selected() {
+                    self.edit_setting(SETTINGS[i], edit);
+                }
+            }
+            Pane::Results => {}
+        }
+    }
+
+    fn select(&mut self, step: isize, results: usize) {
+        let (state, len) = match self.pane {
+            Pane::Ingredients => (&mut self.ingredient, self.catalog.len()),
+            Pane::Target => (&mut self.setting, SETTINGS.len()),
+            Pane::Results => (&mut self.result, results),
+        };
+        if len == 0 {
+            return;
+        }
+        let i = state.selected().unwrap_or(0) as isize + step;
+        state.select(Some(i.clamp(0, len as isize - 1) as usize));
+    }
+
+    /**
+     * Acts on a key press, returning what to do next if it ends this search.
+     */
+    fn key(&mut self, key: KeyEvent, run: Option<&Run>) -> Option<Next> {
+        let results = run.map_or(0, |run| run.ranked.len());
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(Next::Quit)
+            }
+            KeyCode::Char('q') => return Some(Next::Quit),
+            KeyCode::Char('s') => return Some(Next::Search),
+            KeyCode::Char('x') => {
+                if let Some(run) = run {
+                    run.shared_state.stop.store(true, Ordering::Relaxed);
+                }
+            }
+            _ if self.detail => {
+                if matches!(key.code, KeyCode::Esc | KeyCode::Enter | KeyCode::Backspace) {
+                    self.detail = false;
+                }
+            }
+            KeyCode::Tab => {
+                self.pane = match self.pane {
+                    Pane::Ingredients => Pane::Target,
+                    Pane::Target => Pane::Results,
+                    Pane::Results => Pane::Ingredients,
+                }
+            }
+            KeyCode::BackTab => {
+                self.pane = match self.pane {
+                    Pane::Ingredients => Pane::Results,
+                    Pane::Target => Pane::Ingredients,
+                    Pane::Results => Pane::Target,
+                }
+            }
+            KeyCode::Up => self.select(-1, results),
+            KeyCode::Down => self.select(1, results),
+            KeyCode::PageUp => self.select(-(PAGE as isize), results),
+            KeyCode::PageDown => self.select(PAGE as isize, results),
+            KeyCode::Home => self.select(isize::MIN / 2, results),
+            KeyCode::End => self.select(isize::MAX / 2, results),
+            KeyCode::Enter if self.pane == Pane::Results => {
+                self.detail = self.result.selected().is_some_and(|i| i < results)
+            }
+            KeyCode::Left => self.edit(Edit::Less),
+            KeyCode::Right => self.edit(Edit::More),
+            KeyCode::Char(' ') => self.edit(Edit::Toggle),
+            KeyCode::Backspace => self.edit(Edit::Erase),
+            KeyCode::Char(c) if c.is_ascii_digit() => {
+                self.edit(Edit::Digit(c.to_digit(10).unwrap() as usize))
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn block(&self, pane: Pane, title: String) -> Block<'static> {
+        let block = Block::bordered().title(title);
+        if self.pane == pane {
+            block.border_style(Style::new().add_modifier(Modifier::BOLD))
+        } else {
+            block
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, run: Option<&Run>) {
+        let highlight = Style::new().add_modifier(Modifier::REVERSED);
+        let [main, status, help] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+        let [ingredients_area, target_area] = Layout::vertical([
+            Constraint::Min(0),
+            Constraint::Length(SETTINGS.len() as u16 + 2),
+        ])
+        .areas(left);
+
+        let width = self
+            .catalog
+            .iter()
+            .map(|(ingredient, _)| ingredient.name.len())
+            .max()
+            .unwrap_or(0);
+        let ingredients: Vec<ListItem> = self
+            .catalog
+            .iter()
+            .zip(self.included.iter())
+            .map(|((ingredient, available), &included)| {
+                ListItem::new(format!(
+                    "[{}] {:<width$} {:>4} ${}",
+                    if included { 'x' } else { ' ' },
+                    ingredient.name,
+                    available.map_or("any".to_owned(), |n| n.to_string()),
+                    ingredient.price,
+                    width = width
+                ))
+            })
+            .collect();
+        let included = self.included.iter().filter(|&&included| included).count();
+        let list = List::new(ingredients)
+            .block(self.block(
+                Pane::Ingredients,
+                format!(" Ingredients, {} of {} ", included, self.catalog.len()),
+            ))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(list, ingredients_area, &mut self.ingredient);
+
+        let settings: Vec<ListItem> = SETTINGS
+            .iter()
+            .map(|&setting| {
+                ListItem::new(format!(
+                    "{:<14} {}",
+                    App::label(setting),
+                    self.value(setting)
+                ))
+            })
+            .collect();
+        let list = List::new(settings)
+            .block(self.block(Pane::Target, " Target ".to_owned()))
+            .highlight_style(highlight);
+        frame.render_stateful_widget(list, target_area, &mut self.setting);
+
+        let title = format!(
+            " Results, {} ",
+            run.map_or("not searched".to_owned(), Run::status)
+        );
+        let selected = self.result.selected().filter(|_| self.detail);
+        match (run, selected) {
+            (Some(run), Some(i)) if i < run.ranked.len() => {
+                let recipe = &run.ranked[i];
+                let (headline, rows) = output::table(&run.shared_state.target, recipe);
+                let mut lines = vec![Line::from(headline), Line::from("")];
+                lines.extend(rows.into_iter().map(Line::from));
+                for class in run.substitutions.used(&recipe.ingredients) {
+                    lines.push(Line::from(format!("interchangeable: {}", class.join(", "))));
+                }
+                let detail = Paragraph::new(lines)
+                    .block(self.block(Pane::Results, format!(" Recipe #{} ", i + 1)));
+                frame.render_widget(detail, right);
+            }
+            _ => {
+                let target = run.map(|run| &run.shared_state.target);
+                let recipes: Vec<ListItem> = run
+                    .map_or(&[][..], |run| &run.ranked)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, recipe)| {
+                        let attributes = &recipe.attributes;
+                        let names: Vec<String> = output::counts(&recipe.ingredients)
+                            .iter()
+                            .map(|(ingredient, count)| format!("{}x {}", count, ingredient.name))
+                            .collect();
+                        ListItem::new(format!(
+                            "#{:<4} {:>4} magimins, {}, {} sense, ${}: {}",
+                            i + 1,
+                            attributes.magimins.total(),
+                            attributes.stability(target.unwrap()),
+                            attributes.sense_score(),
+                            recipe.cost,
+                            names.join(", ")
+                        ))
+                    })
+                    .collect();
+                let list = List::new(recipes)
+                    .block(self.block(Pane::Results, title))
+                    .highlight_style(highlight);
+                frame.render_stateful_widget(list, right, &mut self.result);
+            }
+        }
+
+        let message = match &self.error {
+            Some(e) => e.clone(),
+            None if self.changed => "Settings changed, press s to search again".to_owned(),
+            None => String::new(),
+        };
+        frame.render_widget(Paragraph::new(message), status);
+        frame.render_widget(
+            Paragraph::new(
+                "Tab pane  ↑↓ select  ←→ change  Space include  0-9 ⌫ type  \
+                 Enter details  s search  x stop  q quit",
+            )
+            .style(Style::new().add_modifier(Modifier::DIM)),
+            help,
+        );
+    }
+
+    /**
+     * Redraws and handles keys until one ends this search.
+     */
+    fn interact(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        mut run: Option<&mut Run>,
+    ) -> Result<Next, String> {
+        loop {
+            if let Some(run) = run.as_mut() {
+                run.catch_up();
+                let results = run.ranked.len();
+                if self.result.selected().is_none_or(|i| i >= results) {
+                    self.result.select(results.checked_sub(1).map(|_| 0));
+                }
+            }
+            terminal
+                .draw(|frame| self.draw(frame, run.as_deref()))
+                .map_err(|e| format!("Can't draw the screen: {}", e))?;
+            if !event::poll(REFRESH_INTERVAL).map_err(|e| e.to_string())? {
+                continue;
+            }
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    if let Some(next) = self.key(key, run.as_deref()) {
+                        return Ok(next);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /**
+     * Searches with the current settings, showing the results as they come in.
+     */
+    fn search(&mut self, terminal: &mut DefaultTerminal) -> Result<Next, String> {
+        self.changed = false;
+        self.detail = false;
+        self.result.select(None);
+        let target = self.target();
+        let args = self.args.clone();
+        let catalog: Vec<(Ingredient, Option<usize>)> = self
+            .catalog
+            .iter()
+            .zip(self.included.iter())
+            .filter(|(_, &included)| included)
+            .map(|(ingredient, _)| ingredient.clone())
+            .collect();
+        // Checked here rather than while editing, where typing a number passes through
+        // values that don't fit the rest of the cauldron.
+        let pool = match self
+            .cauldron
+            .check()
+            .map_err(|e| format!("Can't search: {}", e))
+            .and_then(|_| Pool::new(catalog, &target, &args))
+        {
+            Ok(pool) if pool.ingredients.is_empty() => {
+                Err("None of the ingredients included can be in the recipe".to_owned())
+            }
+            pool => pool,
+        };
+        let pool = match pool {
+            Ok(pool) => pool,
+            Err(e) => {
+                self.error = Some(e);
+                return self.interact(terminal, None);
+            }
+        };
+        self.error = None;
+
+        let substitutions = Substitutions::new(&pool.ingredients, &pool.equivalences);
+        let shared_state = SharedState {
+            ingredients: &pool.ingredients,
+            target: target.clone(),
+            acc: Mutex::new(Vec::new()),
+            max_cost: args.max_cost,
+            start_with: pool.prefix.clone(),
+            stop: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Progress::default()),
+        };
+        let total = matches!(
+            args.mode,
+            SolveAlgorithm::EXACT | SolveAlgorithm::APPROXIMATE
+        )
+        .then(|| SearchSpace::new(&pool.ingredients, target.count).total());
+        let finished = OnceLock::new();
+        // Every search, the one started at launch too, stops after --timeout.
+        let _timer = utils::Timer::start(args.timeout, &shared_state.stop);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let complete = solve(
+                    &args,
+                    &shared_state,
+                    SearchOptions {
+                        threads: args.threads,
+                        roots: Subtree::roots(pool.ingredients.len(), &pool.prefix),
+                        checkpoint: None,
+                    },
+                );
+                finished.set(complete).unwrap();
+            });
+            let mut run = Run {
+                shared_state: &shared_state,
+                substitutions: &substitutions,
+                objective: args.objective(),
+                total,
+                finished: &finished,
+                ranked: Vec::new(),
+                seen: HashSet::new(),
+                taken: 0,
+            };
+            let next = self.interact(terminal, Some(&mut run));
+            // Whatever comes next, this search is over.
+            shared_state.stop.store(true, Ordering::Relaxed);
+            next
+        })
+    }
+}
+
+/**
+ * Searches in a full-screen terminal UI, where the ingredients and target can
+ * be changed and searched again while the ranked results update live.
+ */
+pub fn run(args: Args, catalog: Vec<(Ingredient, Option<usize>)>) -> Result<(), String> {
+    // Anything logged would scribble over the screen.
+    log::set_max_level(log::LevelFilter::Off);
+    let mut app = App {
+        cauldron: Cauldron::new(args.recipe),
+        args,
+        included: vec![true; catalog.len()],
+        catalog,
+        pane: Pane::Ingredients,
+        ingredient: ListState::default().with_selected(Some(0)),
+        setting: ListState::default().with_selected(Some(0)),
+        result: ListState::default(),
+        detail: false,
+        changed: false,
+        error: None,
+    };
+    let mut terminal = ratatui::init();
+    let result = loop {
+        match app.search(&mut terminal) {
+            Ok(Next::Search) => {}
+            Ok(Next::Quit) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    ratatui::restore();
+    result
+}
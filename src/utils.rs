@@ -1,5 +1,9 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
 #[macro_export]
 macro_rules! regex {
@@ -15,3 +19,117 @@ pub fn get_input(filename: &str) -> Box<dyn Iterator<Item = String>> {
             .map(|l| l.unwrap()),
     )
 }
+
+/**
+ * The whole input file, to hand to processes that don't have it.
+ */
+pub fn read_input(filename: &str) -> String {
+    std::fs::read_to_string("input/".to_owned() + filename).unwrap()
+}
+
+/**
+ * Parses a percentage such as "5%" or "2.5" into the number of percent.
+ */
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let value = s
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|e| format!("{}: {}", s, e))?;
+    if !value.is_finite() {
+        return Err(format!("{}: must be a number", s));
+    }
+    if value < 0.0 {
+        return Err(format!("{}: must not be negative", s));
+    }
+    Ok(value)
+}
+
+/**
+ * Parses a probability between 0 and 1, such as "0.3".
+ */
+pub fn parse_chance(s: &str) -> Result<f64, String> {
+    let value = s
+        .trim()
+        .parse::<f64>()
+        .map_err(|e| format!("{}: {}", s, e))?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{}: must be between 0 and 1", s));
+    }
+    Ok(value)
+}
+
+/**
+ * Parses a duration such as "30s", "5m", "1h", "7d" or "250ms". A bare number is seconds.
+ */
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value = value.parse::<f64>().map_err(|e| format!("{}: {}", s, e))?;
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        "d" => value * 86400.0,
+        _ => return Err(format!("{}: unknown unit {}", s, unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("{}: {}", s, e))
+}
+
+/**
+ * Formats a number of seconds the way `parse_duration` reads them, e.g. "1h 5m" or "42s".
+ */
+pub fn format_duration(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return "?".to_owned();
+    }
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/**
+ * 64-bit FNV-1a, a quick hash that stays the same between runs and builds.
+ */
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/**
+ * Hashes an input file's contents, to tell whether it changed between runs.
+ */
+pub fn input_hash(filename: &str) -> u64 {
+    fnv1a(&std::fs::read("input/".to_owned() + filename).unwrap())
+}
+
+/**
+ * Sets a stop flag once a timeout passes, unless dropped before then.
+ */
+pub struct Timer {
+    _cancel: mpsc::Sender<()>,
+}
+
+impl Timer {
+    pub fn start(timeout: Option<Duration>, stop: &Arc<AtomicBool>) -> Timer {
+        let (cancel, cancelled) = mpsc::channel();
+        if let Some(timeout) = timeout {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                // Dropping the sender disconnects the channel, ending the wait early.
+                if cancelled.recv_timeout(timeout) == Err(mpsc::RecvTimeoutError::Timeout) {
+                    stop.store(true, Ordering::Relaxed);
+                }
+            });
+        }
+        Timer { _cancel: cancel }
+    }
+}